@@ -1,8 +1,285 @@
+mod repl;
+
+use lox_compiler::diagnostics::{self, Style};
+use lox_compiler::{
+    Backend, CommentSyntax, EnvironmentValue, ExecutionLimits, Interpreter, LoxError,
+};
+
+use std::io::{IsTerminal, Read};
+use std::process;
+use std::time::{Duration, Instant};
+
+// Exit codes follow sysexits.h, as in Crafting Interpreters.
+const EXIT_USAGE: i32 = 64;
+const EXIT_DATA_ERROR: i32 = 65;
+const EXIT_NO_INPUT: i32 = 66;
+const EXIT_SOFTWARE: i32 = 70;
+
+const MAX_CALL_DEPTH: usize = 1024;
+/// Stack of the thread scripts run on.
+const STACK_SIZE: usize = 64 * 1024 * 1024;
+/// What the tree-walker may use of `STACK_SIZE` before a "Stack overflow" error, the rest
+/// is left to the frames of the binary and the nodes evaluated past the limit.
+const MAX_STACK: usize = STACK_SIZE - 8 * 1024 * 1024;
+
+const USAGE: &str = "Usage:
+    lox                                       start an interactive prompt
+    lox run [options] <file | -> [args...]    run a script, \"-\" reads it from stdin
+
+Options:
+    --time      print the elapsed time after running
+    --tokens    print the scanned tokens before running
+    --ast       print the parsed statements before running
+    --vm        run on the bytecode vm instead of the tree-walker, it never frees
+                objects that refer to each other in a cycle
+    --hash-comments
+                start line comments with \"#\" so \"//\" is floor division
+    --max-call-depth <n>
+                how deep Lox calls may nest before a stack overflow error, 1024 by default,
+                the tree-walker also stops once deeply nested code uses up its stack
+    --max-steps <n>
+                stop the script after n loop iterations and calls
+    --timeout <seconds>
+                stop the script after running this long
+    --max-memory <bytes>
+                fail once strings, lists, instances and environments hold more than this
+    --color <auto|always|never>
+                colour error output, auto colours a terminal unless NO_COLOR is set
+    -h, --help  print this message";
+
+#[derive(Debug)]
+struct RunOptions {
+    time: bool,
+    tokens: bool,
+    ast: bool,
+    vm: bool,
+    comments: CommentSyntax,
+    color: Option<Style>,
+    max_call_depth: usize,
+    limits: ExecutionLimits,
+    path: String,
+    script_args: Vec<String>,
+}
+
 fn main() {
-    let now = std::time::Instant::now();
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
 
-    let data = std::fs::read_to_string("test.lox").unwrap();
-    lox_compiler::interpret(&data, None);
+    match args.first().map(|arg| arg.as_str()) {
+        None => with_stack(|| repl::run_prompt(MAX_CALL_DEPTH)),
+        Some("run") => match parse_run_options(&args[1..]) {
+            Ok(options) => with_stack(|| run_file(options)),
+            Err(message) => exit_with_usage(&message),
+        },
+        Some("-h") | Some("--help") => println!("{}", USAGE),
+        Some(command) => exit_with_usage(&format!("Unknown command \"{}\"", command)),
+    }
+}
+
+fn parse_run_options(args: &[String]) -> Result<RunOptions, String> {
+    let mut options = RunOptions {
+        time: false,
+        tokens: false,
+        ast: false,
+        vm: false,
+        comments: CommentSyntax::DoubleSlash,
+        color: None,
+        max_call_depth: MAX_CALL_DEPTH,
+        limits: ExecutionLimits::default(),
+        path: String::new(),
+        script_args: Vec::new(),
+    };
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--time" => options.time = true,
+            "--tokens" => options.tokens = true,
+            "--ast" => options.ast = true,
+            "--vm" => options.vm = true,
+            "--hash-comments" => options.comments = CommentSyntax::Hash,
+            "--max-call-depth" => {
+                options.max_call_depth = args
+                    .next()
+                    .and_then(|depth| depth.parse().ok())
+                    .ok_or_else(|| String::from("Expect a number after --max-call-depth"))?
+            }
+            "--max-steps" => {
+                options.limits.max_steps = Some(
+                    args.next()
+                        .and_then(|steps| steps.parse().ok())
+                        .ok_or_else(|| String::from("Expect a number after --max-steps"))?,
+                )
+            }
+            "--max-memory" => {
+                options.limits.max_memory = Some(
+                    args.next()
+                        .and_then(|bytes| bytes.parse().ok())
+                        .ok_or_else(|| {
+                            String::from("Expect a number of bytes after --max-memory")
+                        })?,
+                )
+            }
+            "--timeout" => {
+                options.limits.timeout = Some(
+                    args.next()
+                        .and_then(|seconds| Duration::try_from_secs_f64(seconds.parse().ok()?).ok())
+                        .ok_or_else(|| {
+                            String::from("Expect a number of seconds after --timeout")
+                        })?,
+                )
+            }
+            "--color" => {
+                options.color = match args.next().map(|choice| choice.as_str()) {
+                    Some("auto") => None,
+                    Some("always") => Some(Style::Ansi),
+                    Some("never") => Some(Style::Plain),
+                    _ => return Err(String::from("Expect auto, always or never after --color")),
+                }
+            }
+            flag if flag.starts_with('-') && flag != "-" => {
+                return Err(format!("Unknown option \"{}\"", flag))
+            }
+            _ => {
+                options.path = arg.clone();
+                break;
+            }
+        }
+    }
+
+    if options.path.is_empty() {
+        return Err(String::from("Expect a script path"));
+    }
+    options.script_args = args.cloned().collect();
+    Ok(options)
+}
+
+/// Runs `f` on a thread with a stack of `STACK_SIZE`.
+fn with_stack<F>(f: F)
+where
+    F: FnOnce() + Send + 'static,
+{
+    let result = std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(f)
+        .map(|handle| handle.join());
+
+    match result {
+        Ok(Ok(())) => {}
+        Ok(Err(_)) => process::exit(EXIT_SOFTWARE),
+        Err(err) => {
+            eprintln!("Can't reserve a stack of {} bytes: {}", STACK_SIZE, err);
+            process::exit(EXIT_USAGE);
+        }
+    }
+}
+
+fn exit_with_usage(message: &str) -> ! {
+    eprintln!("{}\n\n{}", message, USAGE);
+    process::exit(EXIT_USAGE);
+}
+
+fn read_source(path: &str) -> Result<String, std::io::Error> {
+    if path == "-" {
+        let mut source = String::new();
+        std::io::stdin().read_to_string(&mut source)?;
+        return Ok(source);
+    }
+    std::fs::read_to_string(path)
+}
+
+fn run_file(options: RunOptions) {
+    let source = match read_source(&options.path) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("Can't read {}: {}", options.path, err);
+            process::exit(EXIT_NO_INPUT);
+        }
+    };
+
+    let now = Instant::now();
+
+    let style = options.color.unwrap_or_else(detect_style);
+    let file = if options.path == "-" {
+        "<stdin>"
+    } else {
+        options.path.as_str()
+    };
+    let report = |err: LoxError| -> ! {
+        eprint!("{}", diagnostics::render(&err, file, &source, style));
+        match err {
+            LoxError::RuntimeError(_) | LoxError::LimitExceeded(_) => process::exit(EXIT_SOFTWARE),
+            _ => process::exit(EXIT_DATA_ERROR),
+        }
+    };
+
+    if let Err(err) = dump(&options, &source) {
+        report(err);
+    }
+
+    let backend = if options.vm {
+        Backend::Vm
+    } else {
+        Backend::TreeWalker
+    };
+    let mut interpreter = Interpreter::with_backend(None, backend);
+    interpreter.comments = options.comments;
+    interpreter.set_max_call_depth(options.max_call_depth);
+    interpreter.set_max_stack(MAX_STACK);
+    interpreter.set_limits(options.limits.clone());
+    define_script_args(&mut interpreter, options.script_args.clone());
+
+    let result = lox_compiler::interpret_with(&source, &mut interpreter);
+
+    if options.time {
+        eprintln!("Elapsed: {:?}", now.elapsed());
+    }
+    if let Err(err) = result {
+        report(err);
+    }
+}
+
+fn dump(options: &RunOptions, source: &str) -> Result<(), LoxError> {
+    if options.tokens {
+        for token in lox_compiler::parse_token_with_comments(source, options.comments)?.iter() {
+            println!(
+                "{}:{}\t{:?}\t{}",
+                token.line, token.column, token.token_type, token.lexeme
+            );
+        }
+    }
+    if options.ast {
+        println!(
+            "{:#?}",
+            lox_compiler::parse_with_comments(source, options.comments)?
+        );
+    }
+    Ok(())
+}
+
+/// Exposes the arguments after the script path as `argc()` and `argv(index)`.
+fn define_script_args(interpreter: &mut Interpreter, script_args: Vec<String>) {
+    let argc = script_args.len();
+    interpreter.define_native("argc", 0, move |_| {
+        Ok(EnvironmentValue::Number(argc as f64))
+    });
+    interpreter.define_native("argv", 1, move |args| match &args[0] {
+        EnvironmentValue::Number(index) if *index >= 0_f64 && index.fract() == 0_f64 => {
+            Ok(script_args
+                .get(*index as usize)
+                .map(|arg| EnvironmentValue::String(arg.as_str().into()))
+                .unwrap_or(EnvironmentValue::None))
+        }
+        _ => Err(String::from(
+            "Argument index must be a non-negative integer",
+        )),
+    });
+}
 
-    println!("耗时：{:?}", now.elapsed());
+/// Colours diagnostics when stderr is a terminal and NO_COLOR is unset.
+fn detect_style() -> Style {
+    if std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none() {
+        Style::Ansi
+    } else {
+        Style::Plain
+    }
 }
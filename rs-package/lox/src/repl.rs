@@ -0,0 +1,82 @@
+use lox_compiler::diagnostics;
+use lox_compiler::scanner::tokens::TokensType;
+use lox_compiler::{Interpreter, LoxError};
+
+use std::io::{self, BufRead, Write};
+
+const PROMPT: &str = "> ";
+const CONTINUATION_PROMPT: &str = ". ";
+
+pub fn run_prompt(max_call_depth: usize) {
+    let mut interpreter = Interpreter::new(None);
+    interpreter.set_max_call_depth(max_call_depth);
+    interpreter.set_max_stack(crate::MAX_STACK);
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut buffer = String::new();
+
+    loop {
+        print!(
+            "{}",
+            if buffer.is_empty() {
+                PROMPT
+            } else {
+                CONTINUATION_PROMPT
+            }
+        );
+        io::stdout().flush().unwrap();
+
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            _ => break,
+        };
+        buffer.push_str(&line);
+        buffer.push('\n');
+
+        if is_incomplete(&buffer) {
+            continue;
+        }
+
+        let mut result = lox_compiler::interpret_repl(&buffer, &mut interpreter, true);
+        if let Err(LoxError::ParseError(_)) = result {
+            // Allow a bare expression such as `a + 1` without the trailing ";".
+            let statement = buffer.trim_end().to_string() + ";";
+            let retry = lox_compiler::interpret_repl(&statement, &mut interpreter, true);
+            if !matches!(retry, Err(LoxError::ParseError(_))) {
+                result = retry;
+            }
+        }
+
+        match result {
+            Ok(Some(value)) => println!("{}", interpreter.display(&value)),
+            Ok(None) => {}
+            Err(err) => eprint!(
+                "{}",
+                diagnostics::render(&err, "<repl>", &buffer, crate::detect_style())
+            ),
+        }
+        buffer.clear();
+    }
+    println!();
+}
+
+/// An input is incomplete while a string or a block/grouping is left open.
+fn is_incomplete(code: &str) -> bool {
+    match lox_compiler::parse_token(code) {
+        Ok(tokens) => {
+            let mut depth = 0;
+            for token in tokens.iter() {
+                match token.token_type {
+                    TokensType::LeftBrace | TokensType::LeftParen => depth += 1,
+                    TokensType::RightBrace | TokensType::RightParen => depth -= 1,
+                    _ => {}
+                }
+            }
+            depth > 0
+        }
+        Err(LoxError::ScanError(errors)) => errors
+            .iter()
+            .any(|err| err.message == "Unterminated string"),
+        Err(_) => false,
+    }
+}
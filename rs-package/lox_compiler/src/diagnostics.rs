@@ -0,0 +1,293 @@
+use crate::scanner::scanner::{Error, TraceFrame};
+use crate::LoxError;
+
+use std::fmt::Write;
+
+const RED: &str = "\x1b[1;31m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Style {
+    Plain,
+    /// Colours the header, gutter and underline with ANSI escapes, for terminals.
+    Ansi,
+}
+
+impl Style {
+    fn paint(&self, color: &str, text: &str) -> String {
+        match self {
+            Style::Plain => text.to_string(),
+            Style::Ansi => format!("{}{}{}", color, text, RESET),
+        }
+    }
+}
+
+/// Renders every error of `err` rustc-style, quoting the offending line of `source`:
+///
+/// ```text
+/// error[E0300]: Undefined variable at x
+///  --> main.lox:1:7
+///   |
+/// 1 | print x;
+///   |       ^
+/// ```
+pub fn render(err: &LoxError, file: &str, source: &str, style: Style) -> String {
+    let lines = source.lines().collect::<Vec<_>>();
+    let mut out = String::new();
+
+    for error in err.errors() {
+        render_error(&mut out, error, file, &lines, style);
+        out.push('\n');
+    }
+
+    let count = err.errors().len();
+    let _ = writeln!(
+        out,
+        "{}: could not {} due to {} previous error{}",
+        style.paint(RED, "error"),
+        err.phase(),
+        count,
+        if count == 1 { "" } else { "s" }
+    );
+    out
+}
+
+fn render_error(out: &mut String, error: &Error, file: &str, lines: &[&str], style: Style) {
+    let line_number = error.line.to_string();
+    let gutter = " ".repeat(line_number.len());
+    let bar = style.paint(BLUE, "|");
+
+    let _ = writeln!(
+        out,
+        "{}{}",
+        style.paint(RED, &format!("error[{}]", error.code)),
+        style.paint(BOLD, &format!(": {}", error.message))
+    );
+    let _ = writeln!(
+        out,
+        "{}{} {}:{}:{}",
+        gutter,
+        style.paint(BLUE, "-->"),
+        file,
+        error.line,
+        error.column
+    );
+
+    if let Some(text) = error.line.checked_sub(1).and_then(|index| lines.get(index)) {
+        let (padding, width) = underline(text, error.column, error.length);
+        let _ = writeln!(out, "{} {}", gutter, bar);
+        let _ = writeln!(out, "{} {} {}", style.paint(BLUE, &line_number), bar, text);
+        let _ = writeln!(
+            out,
+            "{} {} {}{}",
+            gutter,
+            bar,
+            padding,
+            style.paint(RED, &"^".repeat(width))
+        );
+    }
+
+    for note in error.notes.iter() {
+        let _ = writeln!(out, "{} {} {}", gutter, style.paint(BLUE, "="), note);
+    }
+
+    if !error.backtrace.is_empty() {
+        let _ = writeln!(out, "{} {} backtrace:", gutter, style.paint(BLUE, "="));
+        render_backtrace(out, &error.backtrace, &gutter);
+    }
+}
+
+/// Deep recursion would bury the error, only both ends of a long backtrace are listed.
+fn render_backtrace(out: &mut String, backtrace: &[TraceFrame], gutter: &str) {
+    const SHOWN: usize = 10;
+
+    for (index, frame) in backtrace.iter().enumerate() {
+        if backtrace.len() > SHOWN * 2 && index == SHOWN {
+            let hidden = backtrace.len() - SHOWN * 2;
+            let _ = writeln!(out, "{}     ... {} more frames", gutter, hidden);
+        }
+        if backtrace.len() <= SHOWN * 2 || index < SHOWN || index >= backtrace.len() - SHOWN {
+            let _ = writeln!(out, "{}     {}", gutter, frame);
+        }
+    }
+}
+
+/// Whitespace leading up to `column` (tabs kept so the caret lines up) and how many
+/// carets fit between there and the end of the line.
+fn underline(text: &str, column: usize, length: usize) -> (String, usize) {
+    let before = column.saturating_sub(1);
+    let padding = text
+        .chars()
+        .take(before)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect::<String>();
+    let remaining = text.chars().count().saturating_sub(before);
+    (padding, length.min(remaining).max(1))
+}
+
+/// Renders `err` as a JSON object for tools such as the web playground, every diagnostic
+/// carries its plain rendering next to the structured fields.
+pub fn render_json(err: &LoxError, file: &str, source: &str) -> String {
+    let lines = source.lines().collect::<Vec<_>>();
+    let diagnostics = err
+        .errors()
+        .iter()
+        .map(|error| {
+            let mut rendered = String::new();
+            render_error(&mut rendered, error, file, &lines, Style::Plain);
+            let source_line = error
+                .line
+                .checked_sub(1)
+                .and_then(|index| lines.get(index))
+                .unwrap_or(&"");
+            let notes = error
+                .notes
+                .iter()
+                .map(|note| json_string(note))
+                .collect::<Vec<_>>()
+                .join(",");
+            let backtrace = error
+                .backtrace
+                .iter()
+                .map(|frame| {
+                    format!(
+                        "{{\"name\":{},\"line\":{}}}",
+                        json_string(&frame.name),
+                        frame.line
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(",");
+            format!(
+                "{{\"code\":{},\"severity\":\"error\",\"message\":{},\"file\":{},\"line\":{},\"column\":{},\"length\":{},\"source_line\":{},\"notes\":[{}],\"backtrace\":[{}],\"rendered\":{}}}",
+                json_string(error.code),
+                json_string(&error.message),
+                json_string(file),
+                error.line,
+                error.column,
+                error.length,
+                json_string(source_line),
+                notes,
+                backtrace,
+                json_string(&rendered)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+
+    format!(
+        "{{\"phase\":{},\"diagnostics\":[{}]}}",
+        json_string(err.phase()),
+        diagnostics
+    )
+}
+
+fn json_string(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(line: usize, column: usize, length: usize, message: &str) -> Error {
+        Error {
+            length,
+            ..Error::new(line, column, "E0300", message.to_string())
+        }
+    }
+
+    #[test]
+    fn json_string_escapes_quotes_and_control_characters() {
+        assert_eq!(json_string(r#"say "hi""#), r#""say \"hi\"""#);
+        assert_eq!(json_string(r"C:\lox"), r#""C:\\lox""#);
+        assert_eq!(json_string("a\nb\r\tc"), r#""a\nb\r\tc""#);
+        assert_eq!(
+            json_string("\u{0}\u{7}\x1b[0m"),
+            r#""\u0000\u0007\u001b[0m""#
+        );
+        assert_eq!(json_string("héllo 你好"), "\"héllo 你好\"");
+    }
+
+    #[test]
+    fn render_json_escapes_every_field() {
+        let err = LoxError::RuntimeError(vec![
+            error(1, 7, 1, "Undefined \"x\"\u{7}").with_note("line one\nline two")
+        ]);
+        let json = render_json(&err, "dir\\main.lox", "print x;");
+
+        assert!(json.starts_with(r#"{"phase":"interpret","diagnostics":[{"code":"E0300""#));
+        assert!(json.contains(r#""message":"Undefined \"x\"\u0007""#));
+        assert!(json.contains(r#""file":"dir\\main.lox""#));
+        assert!(json.contains(r#""source_line":"print x;""#));
+        assert!(json.contains(r#""notes":["line one\nline two"]"#));
+        assert!(!json.contains('\n'));
+    }
+
+    #[test]
+    fn render_counts_columns_in_characters() {
+        let source = "print \"héllo\" + ünknown;";
+        let err = LoxError::RuntimeError(vec![error(1, 17, 7, "Undefined variable at ünknown")]);
+
+        assert_eq!(
+            render(&err, "main.lox", source, Style::Plain),
+            "error[E0300]: Undefined variable at ünknown
+ --> main.lox:1:17
+  |
+1 | print \"héllo\" + ünknown;
+  |                 ^^^^^^^
+
+error: could not interpret due to 1 previous error
+"
+        );
+    }
+
+    #[test]
+    fn render_keeps_tabs_before_the_caret() {
+        let err = LoxError::RuntimeError(vec![error(1, 3, 1, "Undefined variable at x")]);
+        let rendered = render(&err, "main.lox", "\t\tx;", Style::Plain);
+
+        assert!(rendered.contains("  | \t\t^\n"));
+    }
+
+    #[test]
+    fn render_cuts_a_multi_line_span_at_the_end_of_its_first_line() {
+        let source = "var s = \"abc\ndef\";\nprint s;";
+        let err = LoxError::ScanError(vec![error(1, 9, 10, "Unterminated string")]);
+        let rendered = render(&err, "main.lox", source, Style::Plain);
+
+        assert!(rendered.contains("1 | var s = \"abc\n  |         ^^^^\n"));
+        assert!(!rendered.contains("def"));
+    }
+
+    #[test]
+    fn render_skips_the_snippet_of_a_line_past_the_end() {
+        let err = LoxError::ParseError(vec![
+            error(3, 1, 1, "Expect expression"),
+            error(1, 1, 3, "Expect ';'"),
+        ]);
+        let rendered = render(&err, "main.lox", "var", Style::Plain);
+
+        assert!(rendered.starts_with("error[E0300]: Expect expression\n --> main.lox:3:1\n\n"));
+        assert!(rendered.contains("1 | var\n  | ^^^\n"));
+        assert!(rendered.ends_with("error: could not parse due to 2 previous errors\n"));
+    }
+}
@@ -1,274 +1,286 @@
-use super::super::scanner::{scanner::*, tokens::*};
+use super::super::scanner::{interner::Symbol, scanner::*, tokens::*};
+use super::heap::{Gc, Heap, HeapObject, Trace, Tracer};
 use crate::environment::environment_value::*;
+use crate::memory::Charge;
 use crate::semantic::scope_analyst::*;
 
-use std::collections::BTreeMap;
+use std::{collections::BTreeMap, mem};
 
-// #[derive(Debug, Clone)]
-// pub struct Environment {
-//     pub values: BTreeMap<*const u8, EnvironmentValue>,
-//     pub enclosing: Option<Rc<RefCell<Environment>>>,
-// }
-
-// impl Environment {
-//     pub fn new(enclosing: Option<Rc<RefCell<Environment>>>) -> Self {
-//         Environment {
-//             values: BTreeMap::new(),
-//             enclosing,
-//         }
-//     }
-
-//     pub fn define(&mut self, name: *const u8, value: EnvironmentValue) {
-//         self.values.insert(name, value);
-//     }
-
-//     pub fn get(&self, name: &Token) -> Result<EnvironmentValue, Error> {
-//         let name_ptr = ScopeAnalyst::get_scope_key_name(&name.lexeme);
-//         if self.values.contains_key(&name_ptr) {
-//             return Ok(self.values.get(&name_ptr).unwrap().clone());
-//         }
-
-//         if let Some(ref enclosing) = self.enclosing {
-//             return enclosing.borrow_mut().get(name);
-//         }
-//         Err(Error {
-//             line: name.line,
-//             column: name.column,
-//             message: String::from("Undefined variable at ") + name.lexeme.as_str(),
-//         })
-//     }
-
-//     pub fn get_env_by_distance(
-//         env: Rc<RefCell<Environment>>,
-//         distance: usize,
-//     ) -> Rc<RefCell<Environment>> {
-//         let mut environment = env;
-//         let mut distance = distance;
-
-//         loop {
-//             if distance > 0 {
-//                 let e = environment.clone();
-//                 let ee = e.borrow_mut();
-//                 if let Some(ref enclosing) = ee.enclosing {
-//                     environment = enclosing.clone();
-//                     distance -= 1;
-//                 } else {
-//                     break;
-//                 }
-//             } else {
-//                 break;
-//             }
-//         }
-//         environment
-//     }
+/// The locals of one scope by the slots the resolver assigned, `enclosing` is `None`
+/// right inside the global scope.
+#[derive(Debug)]
+pub struct Environment {
+    values: Vec<EnvironmentValue>,
+    enclosing: Option<Gc<Environment>>,
+    charge: Charge,
+}
 
-//     pub fn assign(&mut self, name: &Token, value: EnvironmentValue) -> Result<(), Error> {
-//         let name_ptr = name.lexeme.as_ptr();
-//         if self.values.contains_key(&name_ptr) {
-//             self.define(name_ptr, value);
-//             return Ok(());
-//         }
+impl Environment {
+    pub fn new(enclosing: Option<Gc<Environment>>, values: Vec<EnvironmentValue>) -> Self {
+        let charge = Charge::new(
+            mem::size_of::<Environment>() + values.len() * mem::size_of::<EnvironmentValue>(),
+        );
+        Environment {
+            values,
+            enclosing,
+            charge,
+        }
+    }
+}
 
-//         if let Some(ref enclosing) = self.enclosing {
-//             enclosing.borrow_mut().assign(name, value)?;
-//             return Ok(());
-//         }
+impl Trace for Environment {
+    fn trace(&self, tracer: &mut Tracer) {
+        for value in self.values.iter() {
+            tracer.mark_value(value);
+        }
+        if let Some(enclosing) = self.enclosing {
+            tracer.mark(enclosing);
+        }
+    }
+}
 
-//         Err(Error {
-//             line: name.line,
-//             column: name.column,
-//             message: String::from("Undefined variable at ") + name.lexeme.as_str(),
-//         })
-//     }
-// }
-#[derive(Debug, Clone)]
+/// Scopes of the tree-walker and the heap they live on. The global scope keeps its
+/// variables in `globals` by symbol, `current` is `None` while running top-level code.
+#[derive(Debug)]
 pub struct EnvironmentList {
-    list: Vec<BTreeMap<*const u8, EnvironmentValue>>,
-    pub env_pos: usize,
-    pub previous_vec: Vec<Option<usize>>,
+    pub heap: Heap,
+    current: Option<Gc<Environment>>,
+    /// Scopes of the callers, they are not reachable from the scope of a running function.
+    saved: Vec<Option<Gc<Environment>>>,
+    /// Values held by the interpreter while it evaluates something that may allocate.
+    temp_roots: Vec<EnvironmentValue>,
+    globals: BTreeMap<Symbol, EnvironmentValue>,
+    natives: BTreeMap<Symbol, EnvironmentValue>,
+    charge: Charge,
+}
+
+impl Default for EnvironmentList {
+    fn default() -> Self {
+        EnvironmentList::new()
+    }
 }
 
 impl EnvironmentList {
     pub fn new() -> Self {
-        let list = vec![BTreeMap::new()];
         Self {
-            list,
-            env_pos: 0,
-            previous_vec: vec![None],
+            heap: Heap::default(),
+            current: None,
+            saved: Vec::new(),
+            temp_roots: Vec::new(),
+            globals: BTreeMap::new(),
+            natives: BTreeMap::new(),
+            charge: Charge::default(),
         }
     }
 
-    pub fn define(
-        &mut self,
-        name_ptr: *const u8,
-        value: EnvironmentValue,
-    ) -> Result<&EnvironmentValue, Error> {
-        let env = self.list.get_mut(self.env_pos).unwrap();
-        env.insert(name_ptr, value);
-        Ok(env.get(&name_ptr).unwrap())
+    /// Goes back to the top level, a runtime error may have left scopes open.
+    pub fn reset(&mut self) {
+        self.current = None;
+        self.saved.clear();
+        self.temp_roots.clear();
     }
 
-    pub fn get_by_distance(
-        &self,
-        name: &Token,
-        distance: usize,
-    ) -> Result<&EnvironmentValue, Error> {
-        let name_ptr = ScopeAnalyst::get_scope_key_name(&name.lexeme);
-        let value = self.get_by_distance_default(&name_ptr, distance);
+    pub fn current(&self) -> Option<Gc<Environment>> {
+        self.current
+    }
 
-        if value.is_err() {
-            Err(Error {
-                line: name.line,
-                column: name.column,
-                message: String::from("Undefined variable at ") + name.lexeme.as_str(),
-            })
-        } else {
-            Ok(value.unwrap())
+    /// Puts `object` on the heap, collecting first when enough was allocated since the last
+    /// collection. Anything `object` refers to survives the collection.
+    pub fn alloc<T: HeapObject>(&mut self, object: T) -> Gc<T> {
+        let gc = self.heap.insert(object);
+        if self.heap.should_collect() {
+            self.collect(|tracer| tracer.mark(gc));
         }
+        gc
     }
 
-    pub fn get_by_distance_default(
-        &self,
-        name_ptr: &*const u8,
-        distance: usize,
-    ) -> Result<&EnvironmentValue, ()> {
-        let mut env_pos = self.env_pos;
-
-        for _ in 0..distance {
-            env_pos = if let Some(previous_pos) = self.previous_vec.get(env_pos).unwrap() {
-                *previous_pos
-            } else {
-                break;
+    pub fn collect<F>(&mut self, mark_extra: F)
+    where
+        F: FnOnce(&mut Tracer),
+    {
+        let EnvironmentList {
+            heap,
+            current,
+            saved,
+            temp_roots,
+            globals,
+            natives,
+            ..
+        } = self;
+
+        heap.collect(|tracer| {
+            for value in globals
+                .values()
+                .chain(natives.values())
+                .chain(temp_roots.iter())
+            {
+                tracer.mark_value(value);
+            }
+            for env in saved.iter().chain(std::iter::once(&*current)).flatten() {
+                tracer.mark(*env);
             }
+            mark_extra(tracer);
+        });
+    }
+
+    /// Keeps `value` alive until `unroot_to` drops it, for values held in Rust locals.
+    pub fn root(&mut self, value: &EnvironmentValue) {
+        if matches!(
+            value,
+            EnvironmentValue::LoxClass(_)
+                | EnvironmentValue::LoxFunction(_)
+                | EnvironmentValue::LoxInstance(_)
+                | EnvironmentValue::List(_)
+                | EnvironmentValue::Map(_)
+                | EnvironmentValue::LoxNativeFunction(_)
+        ) {
+            self.temp_roots.push(value.clone());
         }
-        if let Some(env) = self.list.get(env_pos) {
-            let value = env.get(name_ptr);
-            if let Some(value) = value {
-                return Ok(value);
+    }
+
+    pub fn roots_len(&self) -> usize {
+        self.temp_roots.len()
+    }
+
+    pub fn unroot_to(&mut self, len: usize) {
+        self.temp_roots.truncate(len);
+    }
+
+    /// Defines a variable in the current environment and returns its slot, locals must be
+    /// defined in the order the resolver declared them.
+    pub fn define(&mut self, name: Symbol, value: EnvironmentValue) -> usize {
+        match self.current {
+            None => {
+                if self.globals.insert(name, value).is_none() {
+                    self.charge
+                        .grow(mem::size_of::<(Symbol, EnvironmentValue)>());
+                }
+                0
+            }
+            Some(current) => {
+                let env = self.heap.get_mut(current);
+                env.values.push(value);
+                env.charge.grow(mem::size_of::<EnvironmentValue>());
+                env.values.len() - 1
             }
         }
-        Err(())
+    }
+
+    pub fn get_by_distance(
+        &self,
+        name: &Token,
+        scope_slot: ScopeSlot,
+    ) -> Result<&EnvironmentValue, Error> {
+        self.get_by_distance_default(scope_slot).ok_or_else(|| {
+            Error::at(
+                name,
+                "E0300",
+                String::from("Undefined variable at ") + name.lexeme.as_str(),
+            )
+        })
+    }
+
+    pub fn get_by_distance_default(&self, scope_slot: ScopeSlot) -> Option<&EnvironmentValue> {
+        let env = self.ancestor(scope_slot.depth)?;
+        self.heap.get(env).values.get(scope_slot.slot)
     }
 
     pub fn assign_by_distance(
         &mut self,
         name: &Token,
-        distance: usize,
+        scope_slot: ScopeSlot,
         value: EnvironmentValue,
     ) -> Result<(), Error> {
-        let name_ptr = ScopeAnalyst::get_scope_key_name(&name.lexeme);
-
-        let mut env_pos = self.env_pos;
-
-        for _ in 0..distance {
-            env_pos = if let Some(previous_pos) = self.previous_vec.get(env_pos).unwrap() {
-                *previous_pos
-            } else {
-                break;
+        if let Some(env) = self.ancestor(scope_slot.depth) {
+            if let Some(slot) = self.heap.get_mut(env).values.get_mut(scope_slot.slot) {
+                *slot = value;
+                return Ok(());
             }
         }
 
-        if let Some(env) = self.list.get_mut(env_pos) {
-            env.insert(name_ptr, value);
-        }
-        Ok(())
+        Err(Error::at(
+            name,
+            "E0300",
+            String::from("Undefined variable at ") + name.lexeme.as_str(),
+        ))
     }
 
-    pub fn assign(&mut self, name: &Token, value: EnvironmentValue) -> Result<(), Error> {
-        let name_ptr = name.lexeme.as_ptr();
-        let mut env;
-
-        let mut env_pos = self.env_pos;
-
-        loop {
-            env = self.list.get_mut(env_pos);
-
-            if let Some(env) = env {
-                if env.contains_key(&name_ptr) {
-                    env.insert(name_ptr, value);
-                    return Ok(());
-                }
+    /// Overwrites a slot of `env`, the global scope is keyed by name.
+    pub fn assign_at(
+        &mut self,
+        name: Symbol,
+        env: Option<Gc<Environment>>,
+        slot: usize,
+        value: EnvironmentValue,
+    ) {
+        match env {
+            None => {
+                self.globals.insert(name, value);
             }
-
-            env_pos = if let Some(previous_pos) = self.previous_vec.get(env_pos).unwrap() {
-                *previous_pos
-            } else {
-                break;
-            };
+            Some(env) => self.heap.get_mut(env).values[slot] = value,
         }
-
-        Err(Error {
-            line: name.line,
-            column: name.column,
-            message: String::from("Undefined variable at ") + name.lexeme.as_str(),
-        })
     }
 
-    pub fn next(&mut self, previous: Option<usize>) -> usize {
-        let from_pos = self.env_pos;
-        let previous = if let Some(previous) = previous {
-            previous
-        } else {
-            self.env_pos
-        };
+    pub fn get_at(&self, env: Gc<Environment>, slot: usize) -> &EnvironmentValue {
+        &self.heap.get(env).values[slot]
+    }
 
-        for pos in self.env_pos + 1..self.list.len() - 1 {
-            match (self.list.get(pos), self.previous_vec.get(pos).unwrap()) {
-                (Some(_), None) => {
-                    self.previous_vec[pos] = Some(previous);
-                    self.env_pos = pos;
-                    return from_pos;
-                }
-                (_, _) => {}
-            }
+    fn ancestor(&self, distance: usize) -> Option<Gc<Environment>> {
+        let mut env = self.current?;
+        for _ in 0..distance {
+            env = self.heap.get(env).enclosing?;
         }
+        Some(env)
+    }
+
+    /// Opens a scope inside the current one.
+    pub fn begin_scope(&mut self) {
+        let env = self.alloc(Environment::new(self.current, Vec::new()));
+        self.current = Some(env);
+    }
 
-        self.list.push(BTreeMap::new());
-        self.previous_vec.push(None);
+    /// Goes back to the enclosing scope, the environment stays alive while closures use it.
+    pub fn end_scope(&mut self) {
+        if let Some(current) = self.current {
+            self.current = self.heap.get(current).enclosing;
+        }
+    }
 
-        let pos = self.list.len() - 1;
-        self.previous_vec[pos] = Some(previous);
-        self.env_pos = pos;
-        from_pos
+    /// Switches to a new scope inside `closure` holding `values` in its first slots,
+    /// until `leave` goes back to the caller's scope.
+    pub fn enter(&mut self, closure: Option<Gc<Environment>>, values: Vec<EnvironmentValue>) {
+        let env = self.alloc(Environment::new(closure, values));
+        self.saved.push(self.current);
+        self.current = Some(env);
     }
 
-    pub fn back(&mut self) {
-        let previous_pos = self.previous_vec.get(self.env_pos).unwrap().unwrap();
-        self.list.get_mut(self.env_pos).unwrap().clear();
-        self.previous_vec[self.env_pos] = None;
-        self.env_pos = previous_pos;
+    pub fn leave(&mut self) {
+        self.current = self.saved.pop().flatten();
     }
 
-    pub fn go_to_env_by_pos(&mut self, env_pos: usize) {
-        self.env_pos = env_pos;
+    pub fn define_native(&mut self, name: Symbol, value: EnvironmentValue) {
+        self.natives.insert(name, value);
     }
 
     pub fn global_get(&self, name: &Token) -> Result<&EnvironmentValue, Error> {
-        let name_ptr = ScopeAnalyst::get_scope_key_name(&name.lexeme);
-        let value = self.list.get(0).unwrap().get(&name_ptr);
+        let value = self
+            .globals
+            .get(&name.symbol)
+            .or_else(|| self.natives.get(&name.symbol));
 
         if let Some(value) = value {
             Ok(value)
         } else {
-            Err(Error {
-                line: name.line,
-                column: name.column,
-                message: String::from("Undefined variable at ") + name.lexeme.as_str(),
-            })
+            Err(Error::at(
+                name,
+                "E0300",
+                String::from("Undefined variable at ") + name.lexeme.as_str(),
+            ))
         }
     }
 
     pub fn global_assign(&mut self, name: &Token, value: EnvironmentValue) -> Result<(), Error> {
-        let name_ptr = ScopeAnalyst::get_scope_key_name(&name.lexeme);
-        self.list.get_mut(0).unwrap().insert(name_ptr, value);
+        self.globals.insert(name.symbol, value);
         Ok(())
     }
-
-    pub fn get_with_pos(
-        &mut self,
-        name_ptr: &*const u8,
-        pos: usize,
-    ) -> Result<&EnvironmentValue, Error> {
-        Ok(self.list.get(pos).unwrap().get(name_ptr).unwrap())
-    }
 }
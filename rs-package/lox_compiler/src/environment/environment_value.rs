@@ -1,19 +1,57 @@
 // use super::super::scanner::{scanner::*, tokens::*};
+use super::heap::{Gc, Heap};
+use super::lox_list::LoxList;
+use super::lox_map::LoxMap;
+use super::lox_string::LoxString;
 use crate::interpreter::lox_class::LoxClass;
 use crate::interpreter::lox_function::LoxFunction;
 use crate::interpreter::lox_instance::LoxInstance;
+use crate::interpreter::lox_native::LoxNativeFunction;
+use crate::vm::object::{VmBoundMethod, VmClass, VmClosure, VmInstance};
 
 use std::{cell::RefCell, fmt, rc::Rc};
 
+/// Error code of `%` or `//` with a zero right operand.
+pub const DIVISION_BY_ZERO: &str = "E0317";
+
+thread_local! {
+    /// Lists and maps being displayed, one that contains itself is elided the second time.
+    static DISPLAYING: RefCell<Vec<*const ()>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Displays the list or map at `container` with `display`, `None` when it is already
+/// being displayed further out.
+pub fn display_once<F>(container: *const (), display: F) -> Option<String>
+where
+    F: FnOnce() -> String,
+{
+    if DISPLAYING.with(|displaying| displaying.borrow().contains(&container)) {
+        return None;
+    }
+
+    DISPLAYING.with(|displaying| displaying.borrow_mut().push(container));
+    let displayed = display();
+    DISPLAYING.with(|displaying| displaying.borrow_mut().pop());
+    Some(displayed)
+}
+
 #[derive(Debug, Clone)]
 pub enum EnvironmentValue {
-    LoxClass(Rc<RefCell<LoxClass>>),
-    LoxFunction(Rc<RefCell<LoxFunction>>),
-    LoxInstance(Rc<RefCell<LoxInstance>>),
-    // LoxNativeFunction,
+    LoxClass(Gc<LoxClass>),
+    LoxFunction(Gc<LoxFunction>),
+    LoxInstance(Gc<LoxInstance>),
+    LoxNativeFunction(Rc<LoxNativeFunction>),
+    List(Gc<LoxList>),
+    Map(Gc<LoxMap>),
     // LoxNativeClass,
+    VmClosure(Rc<VmClosure>),
+    VmBoundMethod(Rc<VmBoundMethod>),
+    VmClass(Rc<RefCell<VmClass>>),
+    VmInstance(Rc<RefCell<VmInstance>>),
+    VmList(Rc<RefCell<LoxList>>),
+    VmMap(Rc<RefCell<LoxMap>>),
     Number(f64),
-    String(String),
+    String(LoxString),
     Bool(bool),
     None,
 }
@@ -25,18 +63,54 @@ impl fmt::Display for EnvironmentValue {
             "{}",
             match self {
                 EnvironmentValue::Number(num_val) => num_val.to_string(),
-                EnvironmentValue::String(string_val) => string_val.clone(),
+                EnvironmentValue::String(string_val) => string_val.to_string(),
                 EnvironmentValue::Bool(bool_val) => bool_val.to_string(),
                 EnvironmentValue::None => String::from("Nil"),
                 EnvironmentValue::LoxClass(_) => String::from("LoxClass"),
                 EnvironmentValue::LoxFunction(_) => String::from("LoxFunction"),
                 EnvironmentValue::LoxInstance(_) => String::from("LoxInstance"),
+                EnvironmentValue::LoxNativeFunction(_) => String::from("LoxNativeFunction"),
+                EnvironmentValue::List(_) | EnvironmentValue::VmList(_) => {
+                    String::from("LoxList")
+                }
+                EnvironmentValue::Map(_) | EnvironmentValue::VmMap(_) => String::from("LoxMap"),
+                EnvironmentValue::VmClosure(_) | EnvironmentValue::VmBoundMethod(_) => {
+                    String::from("LoxFunction")
+                }
+                EnvironmentValue::VmClass(_) => String::from("LoxClass"),
+                EnvironmentValue::VmInstance(_) => String::from("LoxInstance"),
             }
         )
     }
 }
 
 impl EnvironmentValue {
+    /// How `print` shows the value, lists and maps of the tree-walker are read from `heap`,
+    /// which is `None` on the vm.
+    pub fn display(&self, heap: Option<&Heap>) -> String {
+        match self {
+            EnvironmentValue::List(list) => heap
+                .expect("a heap list without the heap")
+                .get(*list)
+                .display(heap),
+            EnvironmentValue::VmList(list) => list.borrow().display(heap),
+            EnvironmentValue::Map(map) => heap
+                .expect("a heap map without the heap")
+                .get(*map)
+                .display(heap),
+            EnvironmentValue::VmMap(map) => map.borrow().display(heap),
+            value => value.to_string(),
+        }
+    }
+
+    /// How the value shows inside a list or map, strings are quoted.
+    pub fn display_nested(&self, heap: Option<&Heap>) -> String {
+        match self {
+            EnvironmentValue::String(string_val) => format!("{:?}", string_val),
+            value => value.display(heap),
+        }
+    }
+
     pub fn is_truthy(&self) -> bool {
         let mut flag = true;
         match self {
@@ -56,130 +130,171 @@ impl EnvironmentValue {
         }
     }
 
+    pub fn is_zero(&self) -> bool {
+        matches!(self, EnvironmentValue::Number(number) if *number == 0.0)
+    }
+
     // The lt, le, gt, and ge methods of this trait can be called using the <, <=, >, and >= operators, respectively.
     #[inline]
-    pub fn lt(lhs: &EnvironmentValue, rhs: &EnvironmentValue) -> Result<EnvironmentValue, ()> {
+    pub fn lt(lhs: &EnvironmentValue, rhs: &EnvironmentValue) -> Option<EnvironmentValue> {
         match (lhs, rhs) {
             (EnvironmentValue::Number(left), EnvironmentValue::Number(right)) => {
-                return Ok(EnvironmentValue::Bool(left < right))
+                return Some(EnvironmentValue::Bool(left < right))
             }
-            (_, _) => Err(()),
+            (_, _) => None,
         }
     }
 
     #[inline]
-    pub fn le(lhs: &EnvironmentValue, rhs: &EnvironmentValue) -> Result<EnvironmentValue, ()> {
+    pub fn le(lhs: &EnvironmentValue, rhs: &EnvironmentValue) -> Option<EnvironmentValue> {
         match (lhs, rhs) {
             (EnvironmentValue::Number(left), EnvironmentValue::Number(right)) => {
-                return Ok(EnvironmentValue::Bool(left <= right))
+                return Some(EnvironmentValue::Bool(left <= right))
             }
-            (_, _) => Err(()),
+            (_, _) => None,
         }
     }
 
     #[inline]
-    pub fn gt(lhs: &EnvironmentValue, rhs: &EnvironmentValue) -> Result<EnvironmentValue, ()> {
+    pub fn gt(lhs: &EnvironmentValue, rhs: &EnvironmentValue) -> Option<EnvironmentValue> {
         match (lhs, rhs) {
             (EnvironmentValue::Number(left), EnvironmentValue::Number(right)) => {
-                return Ok(EnvironmentValue::Bool(left > right))
+                return Some(EnvironmentValue::Bool(left > right))
             }
-            (_, _) => Err(()),
+            (_, _) => None,
         }
     }
 
     #[inline]
-    pub fn ge(lhs: &EnvironmentValue, rhs: &EnvironmentValue) -> Result<EnvironmentValue, ()> {
+    pub fn ge(lhs: &EnvironmentValue, rhs: &EnvironmentValue) -> Option<EnvironmentValue> {
         match (lhs, rhs) {
             (EnvironmentValue::Number(left), EnvironmentValue::Number(right)) => {
-                return Ok(EnvironmentValue::Bool(left >= right))
+                return Some(EnvironmentValue::Bool(left >= right))
             }
-            (_, _) => Err(()),
+            (_, _) => None,
         }
     }
 
     #[inline]
-    pub fn eq(lhs: &EnvironmentValue, rhs: &EnvironmentValue) -> Result<EnvironmentValue, ()> {
+    pub fn eq(lhs: &EnvironmentValue, rhs: &EnvironmentValue) -> Option<EnvironmentValue> {
         match (lhs, rhs) {
             (EnvironmentValue::Number(left), EnvironmentValue::Number(right)) => {
-                return Ok(EnvironmentValue::Bool(left == right))
+                return Some(EnvironmentValue::Bool(left == right))
             }
             (EnvironmentValue::Bool(left), EnvironmentValue::Bool(right)) => {
-                return Ok(EnvironmentValue::Bool(left == right))
+                return Some(EnvironmentValue::Bool(left == right))
             }
             (EnvironmentValue::String(left), EnvironmentValue::String(right)) => {
-                return Ok(EnvironmentValue::Bool(left == right))
+                return Some(EnvironmentValue::Bool(left == right))
             }
-            (_, _) => Err(()),
+            (_, _) => None,
         }
     }
 
     #[inline]
-    pub fn partial_eq(
-        lhs: &EnvironmentValue,
-        rhs: &EnvironmentValue,
-    ) -> Result<EnvironmentValue, ()> {
+    pub fn partial_eq(lhs: &EnvironmentValue, rhs: &EnvironmentValue) -> Option<EnvironmentValue> {
         match (lhs, rhs) {
             (EnvironmentValue::Number(left), EnvironmentValue::Number(right)) => {
-                return Ok(EnvironmentValue::Bool(left != right))
+                return Some(EnvironmentValue::Bool(left != right))
             }
             (EnvironmentValue::Bool(left), EnvironmentValue::Bool(right)) => {
-                return Ok(EnvironmentValue::Bool(left != right))
+                return Some(EnvironmentValue::Bool(left != right))
             }
             (EnvironmentValue::String(left), EnvironmentValue::String(right)) => {
-                return Ok(EnvironmentValue::Bool(left != right))
+                return Some(EnvironmentValue::Bool(left != right))
             }
-            (_, _) => Err(()),
+            (_, _) => None,
         }
     }
 
     #[inline]
-    pub fn add(lhs: &EnvironmentValue, rhs: &EnvironmentValue) -> Result<EnvironmentValue, ()> {
+    pub fn add(lhs: &EnvironmentValue, rhs: &EnvironmentValue) -> Option<EnvironmentValue> {
         match (lhs, rhs) {
             (EnvironmentValue::Number(left), EnvironmentValue::Number(right)) => {
-                return Ok(EnvironmentValue::Number(left + right))
+                return Some(EnvironmentValue::Number(left + right))
             }
             (EnvironmentValue::String(left), EnvironmentValue::String(right)) => {
-                return Ok(EnvironmentValue::String(left.to_owned() + &*right))
+                return Some(EnvironmentValue::String(LoxString::new(
+                    [&**left, &**right].concat(),
+                )))
+            }
+            (_, _) => None,
+        }
+    }
+
+    #[inline]
+    pub fn sub(lhs: &EnvironmentValue, rhs: &EnvironmentValue) -> Option<EnvironmentValue> {
+        match (lhs, rhs) {
+            (EnvironmentValue::Number(left), EnvironmentValue::Number(right)) => {
+                return Some(EnvironmentValue::Number(left - right))
             }
-            (_, _) => Err(()),
+            (_, _) => None,
         }
     }
 
     #[inline]
-    pub fn sub(lhs: &EnvironmentValue, rhs: &EnvironmentValue) -> Result<EnvironmentValue, ()> {
+    pub fn div(lhs: &EnvironmentValue, rhs: &EnvironmentValue) -> Option<EnvironmentValue> {
         match (lhs, rhs) {
             (EnvironmentValue::Number(left), EnvironmentValue::Number(right)) => {
-                return Ok(EnvironmentValue::Number(left - right))
+                return Some(EnvironmentValue::Number(left / right))
             }
-            (_, _) => Err(()),
+            (_, _) => None,
         }
     }
 
     #[inline]
-    pub fn div(lhs: &EnvironmentValue, rhs: &EnvironmentValue) -> Result<EnvironmentValue, ()> {
+    pub fn mul(lhs: &EnvironmentValue, rhs: &EnvironmentValue) -> Option<EnvironmentValue> {
+        match (lhs, rhs) {
+            (EnvironmentValue::Number(left), EnvironmentValue::Number(right)) => {
+                return Some(EnvironmentValue::Number(left * right))
+            }
+            (_, _) => None,
+        }
+    }
+
+    /// The remainder of flooring division, it has the sign of `rhs` as in `-7 % 3 == 2`.
+    #[inline]
+    pub(crate) fn rem(lhs: &EnvironmentValue, rhs: &EnvironmentValue) -> Option<EnvironmentValue> {
+        match (lhs, rhs) {
+            (EnvironmentValue::Number(left), EnvironmentValue::Number(right)) => {
+                let remainder = left % right;
+                if remainder != 0.0 && (remainder < 0.0) != (*right < 0.0) {
+                    return Some(EnvironmentValue::Number(remainder + right));
+                }
+                Some(EnvironmentValue::Number(remainder))
+            }
+            (_, _) => None,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn floor_div(
+        lhs: &EnvironmentValue,
+        rhs: &EnvironmentValue,
+    ) -> Option<EnvironmentValue> {
         match (lhs, rhs) {
             (EnvironmentValue::Number(left), EnvironmentValue::Number(right)) => {
-                return Ok(EnvironmentValue::Number(left / right))
+                Some(EnvironmentValue::Number((left / right).floor()))
             }
-            (_, _) => Err(()),
+            (_, _) => None,
         }
     }
 
     #[inline]
-    pub fn mul(lhs: &EnvironmentValue, rhs: &EnvironmentValue) -> Result<EnvironmentValue, ()> {
+    pub(crate) fn pow(lhs: &EnvironmentValue, rhs: &EnvironmentValue) -> Option<EnvironmentValue> {
         match (lhs, rhs) {
             (EnvironmentValue::Number(left), EnvironmentValue::Number(right)) => {
-                return Ok(EnvironmentValue::Number(left * right))
+                Some(EnvironmentValue::Number(left.powf(*right)))
             }
-            (_, _) => Err(()),
+            (_, _) => None,
         }
     }
 
     #[inline]
-    pub fn neg(lhs: &EnvironmentValue) -> Result<EnvironmentValue, ()> {
+    pub fn neg(lhs: &EnvironmentValue) -> Option<EnvironmentValue> {
         match lhs {
-            EnvironmentValue::Number(left) => return Ok(EnvironmentValue::Number(-left)),
-            _ => Err(()),
+            EnvironmentValue::Number(left) => return Some(EnvironmentValue::Number(-left)),
+            _ => None,
         }
     }
 }
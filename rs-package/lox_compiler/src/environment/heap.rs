@@ -0,0 +1,231 @@
+use super::environment::Environment;
+use super::environment_value::EnvironmentValue;
+use super::lox_list::LoxList;
+use super::lox_map::LoxMap;
+use crate::interpreter::{
+    lox_class::LoxClass, lox_function::LoxFunction, lox_instance::LoxInstance,
+};
+
+use std::{fmt, marker::PhantomData};
+
+/// Live objects below which the heap is never collected.
+const MIN_NEXT_GC: usize = 1024;
+
+/// Handle to an object on the tree-walker's `Heap`, only valid while the object is reachable
+/// from the roots the interpreter hands to the collector.
+pub struct Gc<T> {
+    index: u32,
+    marker: PhantomData<T>,
+}
+
+impl<T> Clone for Gc<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Gc<T> {}
+
+impl<T> PartialEq for Gc<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index
+    }
+}
+
+impl<T> Eq for Gc<T> {}
+
+impl<T> fmt::Debug for Gc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Gc({})", self.index)
+    }
+}
+
+#[derive(Debug)]
+pub enum Object {
+    Environment(Environment),
+    Function(LoxFunction),
+    Class(LoxClass),
+    Instance(LoxInstance),
+    List(LoxList),
+    Map(LoxMap),
+}
+
+/// A type that lives on the heap as one of the `Object` variants.
+pub trait HeapObject: Sized {
+    fn into_object(self) -> Object;
+    fn from_object(object: &Object) -> Option<&Self>;
+    fn from_object_mut(object: &mut Object) -> Option<&mut Self>;
+}
+
+macro_rules! heap_object {
+    ($type:ty, $variant:ident) => {
+        impl HeapObject for $type {
+            fn into_object(self) -> Object {
+                Object::$variant(self)
+            }
+
+            fn from_object(object: &Object) -> Option<&Self> {
+                match object {
+                    Object::$variant(value) => Some(value),
+                    _ => None,
+                }
+            }
+
+            fn from_object_mut(object: &mut Object) -> Option<&mut Self> {
+                match object {
+                    Object::$variant(value) => Some(value),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+heap_object!(Environment, Environment);
+heap_object!(LoxFunction, Function);
+heap_object!(LoxClass, Class);
+heap_object!(LoxInstance, Instance);
+heap_object!(LoxList, List);
+heap_object!(LoxMap, Map);
+
+/// Reports the handles an object holds so the collector can mark them.
+pub trait Trace {
+    fn trace(&self, tracer: &mut Tracer);
+}
+
+impl Trace for Object {
+    fn trace(&self, tracer: &mut Tracer) {
+        match self {
+            Object::Environment(environment) => environment.trace(tracer),
+            Object::Function(function) => function.trace(tracer),
+            Object::Class(class) => class.trace(tracer),
+            Object::Instance(instance) => instance.trace(tracer),
+            Object::List(list) => list.trace(tracer),
+            Object::Map(map) => map.trace(tracer),
+        }
+    }
+}
+
+/// Marks of one collection, objects are greyed when first reached and traced later.
+pub struct Tracer {
+    marks: Vec<bool>,
+    grey: Vec<u32>,
+}
+
+impl Tracer {
+    pub fn mark<T>(&mut self, gc: Gc<T>) {
+        let index = gc.index as usize;
+        if !self.marks[index] {
+            self.marks[index] = true;
+            self.grey.push(gc.index);
+        }
+    }
+
+    pub fn mark_value(&mut self, value: &EnvironmentValue) {
+        match value {
+            EnvironmentValue::LoxClass(class) => self.mark(*class),
+            EnvironmentValue::LoxFunction(function) => self.mark(*function),
+            EnvironmentValue::LoxInstance(instance) => self.mark(*instance),
+            EnvironmentValue::List(list) => self.mark(*list),
+            EnvironmentValue::Map(map) => self.mark(*map),
+            EnvironmentValue::LoxNativeFunction(native) => {
+                if let Some(receiver) = native.receiver() {
+                    self.mark_value(receiver);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Environments, functions, classes, instances, lists and maps of the tree-walker, freed by a
+/// mark-sweep collection once nothing reachable refers to them any more.
+#[derive(Debug)]
+pub struct Heap {
+    objects: Vec<Option<Object>>,
+    free: Vec<u32>,
+    live: usize,
+    next_gc: usize,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Heap {
+            objects: Vec::new(),
+            free: Vec::new(),
+            live: 0,
+            next_gc: MIN_NEXT_GC,
+        }
+    }
+}
+
+impl Heap {
+    pub fn insert<T: HeapObject>(&mut self, object: T) -> Gc<T> {
+        let object = Some(object.into_object());
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.objects[index as usize] = object;
+                index
+            }
+            None => {
+                self.objects.push(object);
+                (self.objects.len() - 1) as u32
+            }
+        };
+        self.live += 1;
+
+        Gc {
+            index,
+            marker: PhantomData,
+        }
+    }
+
+    pub fn get<T: HeapObject>(&self, gc: Gc<T>) -> &T {
+        self.objects[gc.index as usize]
+            .as_ref()
+            .and_then(T::from_object)
+            .expect("dangling heap handle")
+    }
+
+    pub fn get_mut<T: HeapObject>(&mut self, gc: Gc<T>) -> &mut T {
+        self.objects[gc.index as usize]
+            .as_mut()
+            .and_then(T::from_object_mut)
+            .expect("dangling heap handle")
+    }
+
+    pub fn live(&self) -> usize {
+        self.live
+    }
+
+    /// Twice as many objects are live as after the last collection.
+    pub fn should_collect(&self) -> bool {
+        self.live >= self.next_gc
+    }
+
+    /// Frees every object that isn't reachable from what `mark_roots` marks.
+    pub fn collect<F>(&mut self, mark_roots: F)
+    where
+        F: FnOnce(&mut Tracer),
+    {
+        let mut tracer = Tracer {
+            marks: vec![false; self.objects.len()],
+            grey: Vec::new(),
+        };
+        mark_roots(&mut tracer);
+
+        while let Some(index) = tracer.grey.pop() {
+            if let Some(object) = &self.objects[index as usize] {
+                object.trace(&mut tracer);
+            }
+        }
+
+        for (index, marked) in tracer.marks.into_iter().enumerate() {
+            if !marked && self.objects[index].take().is_some() {
+                self.free.push(index as u32);
+                self.live -= 1;
+            }
+        }
+        self.next_gc = (self.live * 2).max(MIN_NEXT_GC);
+    }
+}
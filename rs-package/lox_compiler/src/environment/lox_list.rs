@@ -0,0 +1,206 @@
+use super::environment_value::{display_once, EnvironmentValue};
+use super::heap::{Heap, Trace, Tracer};
+use crate::interpreter::lox_native::LoxNativeFunction;
+use crate::memory::Charge;
+
+use std::{cell::RefCell, mem, rc::Rc};
+
+/// Error code of a list index that isn't a whole number within the list.
+pub const INDEX_OUT_OF_RANGE: &str = "E0313";
+/// Error code of indexing a value that is neither a list nor a map.
+pub const NOT_INDEXABLE: &str = "E0314";
+
+/// A Lox list, every value referring to it shares the same elements. The tree-walker's
+/// lists live on its `Heap`, the vm's are reference counted.
+#[derive(Debug)]
+pub struct LoxList {
+    values: Vec<EnvironmentValue>,
+    charge: Charge,
+}
+
+impl LoxList {
+    pub fn new(values: Vec<EnvironmentValue>) -> Self {
+        let charge = Charge::new(
+            mem::size_of::<LoxList>() + values.len() * mem::size_of::<EnvironmentValue>(),
+        );
+        LoxList { values, charge }
+    }
+
+    pub fn values(&self) -> &[EnvironmentValue] {
+        &self.values
+    }
+
+    pub fn get(
+        &self,
+        index: &EnvironmentValue,
+    ) -> Result<EnvironmentValue, (&'static str, String)> {
+        let index = self.position(index)?;
+        Ok(self.values[index].clone())
+    }
+
+    pub fn set(
+        &mut self,
+        index: &EnvironmentValue,
+        value: EnvironmentValue,
+    ) -> Result<(), (&'static str, String)> {
+        let index = self.position(index)?;
+        self.values[index] = value;
+        Ok(())
+    }
+
+    pub fn push(&mut self, value: EnvironmentValue) {
+        self.values.push(value);
+        self.charge.grow(mem::size_of::<EnvironmentValue>());
+    }
+
+    pub fn pop(&mut self) -> Option<EnvironmentValue> {
+        let value = self.values.pop()?;
+        self.charge.shrink(mem::size_of::<EnvironmentValue>());
+        Some(value)
+    }
+
+    /// Elements from `start` up to but not including `end`, as a new list.
+    pub fn slice(
+        &self,
+        start: &EnvironmentValue,
+        end: &EnvironmentValue,
+    ) -> Result<LoxList, String> {
+        match (
+            bound(start, self.values.len()),
+            bound(end, self.values.len()),
+        ) {
+            (Some(start), Some(end)) if start <= end => {
+                Ok(LoxList::new(self.values[start..end].to_vec()))
+            }
+            _ => Err(format!(
+                "Slice {}..{} is out of range for a list of length {}",
+                start,
+                end,
+                self.values.len()
+            )),
+        }
+    }
+
+    fn position(&self, index: &EnvironmentValue) -> Result<usize, (&'static str, String)> {
+        let message = match index {
+            EnvironmentValue::Number(number) if number.fract() == 0.0 => {
+                match bound(index, self.values.len()) {
+                    Some(index) if index < self.values.len() => return Ok(index),
+                    _ => format!(
+                        "Index {} is out of range for a list of length {}",
+                        number,
+                        self.values.len()
+                    ),
+                }
+            }
+            _ => format!("List index must be a whole number, got {}", index),
+        };
+        Err((INDEX_OUT_OF_RANGE, message))
+    }
+
+    /// The built-in method `name`, bound to `list`, a `List` or a `VmList`.
+    pub fn method(list: &EnvironmentValue, name: &str) -> Option<EnvironmentValue> {
+        let native = match name {
+            "push" => LoxNativeFunction::new("push", 1, Rc::new(list_push)),
+            "pop" => LoxNativeFunction::new("pop", 0, Rc::new(list_pop)),
+            "len" => LoxNativeFunction::new("len", 0, Rc::new(list_len)),
+            "slice" => LoxNativeFunction::new("slice", 2, Rc::new(list_slice)),
+            _ => return None,
+        };
+        Some(EnvironmentValue::LoxNativeFunction(Rc::new(
+            native.bind(list.clone()),
+        )))
+    }
+
+    /// How `print` shows the list, lists and maps in it are read from `heap`.
+    pub fn display(&self, heap: Option<&Heap>) -> String {
+        let elements = display_once(self as *const LoxList as *const (), || {
+            self.values
+                .iter()
+                .map(|value| value.display_nested(heap))
+                .collect::<Vec<_>>()
+                .join(", ")
+        });
+
+        match elements {
+            Some(elements) => format!("[{}]", elements),
+            None => String::from("[...]"),
+        }
+    }
+}
+
+impl Trace for LoxList {
+    fn trace(&self, tracer: &mut Tracer) {
+        for value in self.values.iter() {
+            tracer.mark_value(value);
+        }
+    }
+}
+
+/// `list` as a value, on the tree-walker's `heap` or reference counted for the vm.
+pub fn new_list(heap: Option<&mut Heap>, list: LoxList) -> EnvironmentValue {
+    match heap {
+        Some(heap) => EnvironmentValue::List(heap.insert(list)),
+        None => EnvironmentValue::VmList(Rc::new(RefCell::new(list))),
+    }
+}
+
+/// `value` as a position in `0..=max`.
+fn bound(value: &EnvironmentValue, max: usize) -> Option<usize> {
+    match value {
+        EnvironmentValue::Number(number)
+            if number.fract() == 0.0 && *number >= 0.0 && *number <= max as f64 =>
+        {
+            Some(*number as usize)
+        }
+        _ => None,
+    }
+}
+
+/// Runs `f` on the list a method was called on.
+fn with_receiver<F, R>(heap: Option<&mut Heap>, args: &[EnvironmentValue], f: F) -> R
+where
+    F: FnOnce(&mut LoxList) -> R,
+{
+    match &args[0] {
+        EnvironmentValue::List(list) => {
+            f(heap.expect("a heap list without the heap").get_mut(*list))
+        }
+        EnvironmentValue::VmList(list) => f(&mut list.borrow_mut()),
+        _ => unreachable!(),
+    }
+}
+
+fn list_push(
+    heap: Option<&mut Heap>,
+    args: &[EnvironmentValue],
+) -> Result<EnvironmentValue, String> {
+    with_receiver(heap, args, |list| list.push(args[1].clone()));
+    Ok(EnvironmentValue::None)
+}
+
+fn list_pop(
+    heap: Option<&mut Heap>,
+    args: &[EnvironmentValue],
+) -> Result<EnvironmentValue, String> {
+    with_receiver(heap, args, |list| list.pop())
+        .ok_or_else(|| String::from("Cannot pop from an empty list"))
+}
+
+fn list_len(
+    heap: Option<&mut Heap>,
+    args: &[EnvironmentValue],
+) -> Result<EnvironmentValue, String> {
+    let len = with_receiver(heap, args, |list| list.values.len());
+    Ok(EnvironmentValue::Number(len as f64))
+}
+
+fn list_slice(
+    mut heap: Option<&mut Heap>,
+    args: &[EnvironmentValue],
+) -> Result<EnvironmentValue, String> {
+    let slice = with_receiver(heap.as_deref_mut(), args, |list| {
+        list.slice(&args[1], &args[2])
+    })?;
+    Ok(new_list(heap, slice))
+}
@@ -0,0 +1,244 @@
+use super::environment_value::{display_once, EnvironmentValue};
+use super::heap::{Heap, Trace, Tracer};
+use super::lox_list::{new_list, LoxList};
+use crate::interpreter::lox_native::LoxNativeFunction;
+use crate::memory::Charge;
+
+use std::{collections::HashMap, mem, rc::Rc};
+
+/// Error code of a map key that is not a number, string or boolean.
+pub const INVALID_KEY: &str = "E0315";
+/// Error code of reading a key that is not in the map.
+pub const MISSING_KEY: &str = "E0316";
+
+/// A key as `EnvironmentValue::eq` compares it, `-0` and `0` are the same key.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum MapKey {
+    Number(u64),
+    String(String),
+    Bool(bool),
+}
+
+impl MapKey {
+    fn new(value: &EnvironmentValue) -> Result<Self, (&'static str, String)> {
+        match value {
+            EnvironmentValue::Number(number) if !number.is_nan() => {
+                Ok(MapKey::Number((number + 0.0).to_bits()))
+            }
+            EnvironmentValue::String(string_val) => Ok(MapKey::String(string_val.to_string())),
+            EnvironmentValue::Bool(bool_val) => Ok(MapKey::Bool(*bool_val)),
+            _ => Err((
+                INVALID_KEY,
+                format!(
+                    "Map keys must be numbers, strings or booleans, got {}",
+                    value
+                ),
+            )),
+        }
+    }
+
+    fn value(&self) -> EnvironmentValue {
+        match self {
+            MapKey::Number(bits) => EnvironmentValue::Number(f64::from_bits(*bits)),
+            MapKey::String(string_val) => EnvironmentValue::String(string_val.as_str().into()),
+            MapKey::Bool(bool_val) => EnvironmentValue::Bool(*bool_val),
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            MapKey::String(string_val) => string_val.len(),
+            _ => 0,
+        }
+    }
+}
+
+/// A Lox map, every value referring to it shares the same entries. Keys are kept in
+/// the order they were first inserted. The tree-walker's maps live on its `Heap`, the vm's
+/// are reference counted.
+#[derive(Debug)]
+pub struct LoxMap {
+    entries: HashMap<MapKey, (u64, EnvironmentValue)>,
+    next_order: u64,
+    charge: Charge,
+}
+
+impl LoxMap {
+    pub fn new(
+        entries: Vec<(EnvironmentValue, EnvironmentValue)>,
+    ) -> Result<Self, (&'static str, String)> {
+        let mut map = LoxMap {
+            entries: HashMap::with_capacity(entries.len()),
+            next_order: 0,
+            charge: Charge::new(mem::size_of::<LoxMap>()),
+        };
+        for (key, value) in entries.into_iter() {
+            map.set(&key, value)?;
+        }
+        Ok(map)
+    }
+
+    pub fn get(&self, key: &EnvironmentValue) -> Result<EnvironmentValue, (&'static str, String)> {
+        match self.entries.get(&MapKey::new(key)?) {
+            Some((_, value)) => Ok(value.clone()),
+            None => Err((
+                MISSING_KEY,
+                format!("Key {} is not in the map", key.display_nested(None)),
+            )),
+        }
+    }
+
+    pub fn set(
+        &mut self,
+        key: &EnvironmentValue,
+        value: EnvironmentValue,
+    ) -> Result<(), (&'static str, String)> {
+        let key = MapKey::new(key)?;
+        if let Some(entry) = self.entries.get_mut(&key) {
+            entry.1 = value;
+            return Ok(());
+        }
+
+        self.charge
+            .grow(mem::size_of::<(MapKey, (u64, EnvironmentValue))>() + key.size());
+        self.entries.insert(key, (self.next_order, value));
+        self.next_order += 1;
+        Ok(())
+    }
+
+    pub fn has(&self, key: &EnvironmentValue) -> Result<bool, (&'static str, String)> {
+        Ok(self.entries.contains_key(&MapKey::new(key)?))
+    }
+
+    /// Removes `key`, returning the value it had.
+    pub fn remove(
+        &mut self,
+        key: &EnvironmentValue,
+    ) -> Result<Option<EnvironmentValue>, (&'static str, String)> {
+        let key = MapKey::new(key)?;
+        let removed = self.entries.remove(&key).map(|(_, value)| value);
+        if removed.is_some() {
+            self.charge
+                .shrink(mem::size_of::<(MapKey, (u64, EnvironmentValue))>() + key.size());
+        }
+        Ok(removed)
+    }
+
+    /// Entries in insertion order.
+    fn ordered(&self) -> Vec<(&MapKey, &EnvironmentValue)> {
+        let mut entries = self
+            .entries
+            .iter()
+            .map(|(key, (order, value))| (order, key, value))
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|(order, _, _)| **order);
+        entries
+            .into_iter()
+            .map(|(_, key, value)| (key, value))
+            .collect()
+    }
+
+    /// The built-in method `name`, bound to `map`, a `Map` or a `VmMap`.
+    pub fn method(map: &EnvironmentValue, name: &str) -> Option<EnvironmentValue> {
+        let native = match name {
+            "has" => LoxNativeFunction::new("has", 1, Rc::new(map_has)),
+            "remove" => LoxNativeFunction::new("remove", 1, Rc::new(map_remove)),
+            "keys" => LoxNativeFunction::new("keys", 0, Rc::new(map_keys)),
+            "values" => LoxNativeFunction::new("values", 0, Rc::new(map_values)),
+            "len" => LoxNativeFunction::new("len", 0, Rc::new(map_len)),
+            _ => return None,
+        };
+        Some(EnvironmentValue::LoxNativeFunction(Rc::new(
+            native.bind(map.clone()),
+        )))
+    }
+}
+
+impl LoxMap {
+    /// How `print` shows the map, lists and maps in it are read from `heap`.
+    pub fn display(&self, heap: Option<&Heap>) -> String {
+        let entries = display_once(self as *const LoxMap as *const (), || {
+            self.ordered()
+                .into_iter()
+                .map(|(key, value)| {
+                    format!(
+                        "{}: {}",
+                        key.value().display_nested(heap),
+                        value.display_nested(heap)
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        });
+
+        match entries {
+            Some(entries) => format!("{{{}}}", entries),
+            None => String::from("{...}"),
+        }
+    }
+}
+
+impl Trace for LoxMap {
+    fn trace(&self, tracer: &mut Tracer) {
+        for (_, value) in self.entries.values() {
+            tracer.mark_value(value);
+        }
+    }
+}
+
+/// Runs `f` on the map a method was called on.
+fn with_receiver<F, R>(heap: Option<&mut Heap>, args: &[EnvironmentValue], f: F) -> R
+where
+    F: FnOnce(&mut LoxMap) -> R,
+{
+    match &args[0] {
+        EnvironmentValue::Map(map) => f(heap.expect("a heap map without the heap").get_mut(*map)),
+        EnvironmentValue::VmMap(map) => f(&mut map.borrow_mut()),
+        _ => unreachable!(),
+    }
+}
+
+fn map_has(heap: Option<&mut Heap>, args: &[EnvironmentValue]) -> Result<EnvironmentValue, String> {
+    let has = with_receiver(heap, args, |map| map.has(&args[1])).map_err(|(_, message)| message)?;
+    Ok(EnvironmentValue::Bool(has))
+}
+
+fn map_remove(
+    heap: Option<&mut Heap>,
+    args: &[EnvironmentValue],
+) -> Result<EnvironmentValue, String> {
+    let removed =
+        with_receiver(heap, args, |map| map.remove(&args[1])).map_err(|(_, message)| message)?;
+    Ok(removed.unwrap_or(EnvironmentValue::None))
+}
+
+fn map_keys(
+    mut heap: Option<&mut Heap>,
+    args: &[EnvironmentValue],
+) -> Result<EnvironmentValue, String> {
+    let keys = with_receiver(heap.as_deref_mut(), args, |map| {
+        map.ordered()
+            .into_iter()
+            .map(|(key, _)| key.value())
+            .collect()
+    });
+    Ok(new_list(heap, LoxList::new(keys)))
+}
+
+fn map_values(
+    mut heap: Option<&mut Heap>,
+    args: &[EnvironmentValue],
+) -> Result<EnvironmentValue, String> {
+    let values = with_receiver(heap.as_deref_mut(), args, |map| {
+        map.ordered()
+            .into_iter()
+            .map(|(_, value)| value.clone())
+            .collect()
+    });
+    Ok(new_list(heap, LoxList::new(values)))
+}
+
+fn map_len(heap: Option<&mut Heap>, args: &[EnvironmentValue]) -> Result<EnvironmentValue, String> {
+    let len = with_receiver(heap, args, |map| map.entries.len());
+    Ok(EnvironmentValue::Number(len as f64))
+}
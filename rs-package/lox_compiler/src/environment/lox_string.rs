@@ -0,0 +1,62 @@
+use crate::memory::Charge;
+
+use std::{fmt, ops::Deref, rc::Rc};
+
+/// A Lox string value, its bytes count towards the memory limit while it is alive. Strings
+/// are immutable so clones share the bytes, which are counted once.
+#[derive(Clone, Default)]
+pub struct LoxString(Rc<StringValue>);
+
+#[derive(Default)]
+struct StringValue {
+    value: String,
+    _charge: Charge,
+}
+
+impl LoxString {
+    pub fn new(value: String) -> Self {
+        let charge = Charge::new(value.len());
+        LoxString(Rc::new(StringValue {
+            value,
+            _charge: charge,
+        }))
+    }
+}
+
+impl Deref for LoxString {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0.value
+    }
+}
+
+impl From<String> for LoxString {
+    fn from(value: String) -> Self {
+        LoxString::new(value)
+    }
+}
+
+impl From<&str> for LoxString {
+    fn from(value: &str) -> Self {
+        LoxString::new(value.to_string())
+    }
+}
+
+impl PartialEq for LoxString {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.value == other.0.value
+    }
+}
+
+impl fmt::Display for LoxString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.value.fmt(f)
+    }
+}
+
+impl fmt::Debug for LoxString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.value.fmt(f)
+    }
+}
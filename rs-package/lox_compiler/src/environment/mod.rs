@@ -1,2 +1,6 @@
 pub mod environment;
 pub mod environment_value;
+pub mod heap;
+pub mod lox_list;
+pub mod lox_map;
+pub mod lox_string;
@@ -0,0 +1,52 @@
+use crate::scanner::scanner::Error;
+
+use std::fmt;
+
+#[derive(Debug, Clone)]
+pub enum LoxError {
+    ScanError(Vec<Error>),
+    ParseError(Vec<Error>),
+    ResolveError(Vec<Error>),
+    RuntimeError(Vec<Error>),
+    /// The script was stopped by its `ExecutionLimits`.
+    LimitExceeded(Vec<Error>),
+}
+
+impl LoxError {
+    pub fn errors(&self) -> &Vec<Error> {
+        match self {
+            LoxError::ScanError(errors)
+            | LoxError::ParseError(errors)
+            | LoxError::ResolveError(errors)
+            | LoxError::RuntimeError(errors)
+            | LoxError::LimitExceeded(errors) => errors,
+        }
+    }
+
+    pub fn phase(&self) -> &'static str {
+        match self {
+            LoxError::ScanError(_) => "scan tokens",
+            LoxError::ParseError(_) => "parse",
+            LoxError::ResolveError(_) => "resolve",
+            LoxError::RuntimeError(_) | LoxError::LimitExceeded(_) => "interpret",
+        }
+    }
+}
+
+impl fmt::Display for LoxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let errors = self
+            .errors()
+            .iter()
+            .map(|err| format!("{} \n", err))
+            .collect::<String>();
+        write!(
+            f,
+            "\n\n******\nOops! {} errors:\n{}******\n\n",
+            self.phase(),
+            errors
+        )
+    }
+}
+
+impl std::error::Error for LoxError {}
@@ -0,0 +1,13 @@
+use crate::environment::environment_value::EnvironmentValue;
+use crate::interpreter::interpreter::Interpreter;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub fn define_native(interpreter: &mut Interpreter) {
+    interpreter.define_native("clock", 0, |_| {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|err| err.to_string())?;
+        Ok(EnvironmentValue::Number(now.as_secs_f64()))
+    });
+}
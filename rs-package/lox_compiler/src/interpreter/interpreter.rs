@@ -1,50 +1,162 @@
-use crate::environment::{environment::*, environment_value::*};
+use crate::environment::{
+    environment::*, environment_value::*, heap::Heap, lox_list::*, lox_map::*,
+};
+use crate::limits::{Budget, ExecutionLimits};
 use crate::parser::{expression::*, statement::*};
-use crate::scanner::{scanner::Error, tokens::*};
+use crate::scanner::{
+    interner::*,
+    scanner::{CommentSyntax, Error, TraceFrame},
+    tokens::*,
+};
 use crate::semantic::scope_analyst::*;
-use crate::utils::utils::get_rc_ref_address;
 
+use super::define_native::define_native;
 use super::lox_class::*;
 use super::lox_function::LoxFunction;
-use std::{cell::RefCell, collections::BTreeMap, rc::Rc};
+use super::lox_instance::LoxInstance;
+use super::lox_native::LoxNativeFunction;
+use crate::vm::machine::Vm;
+use std::{collections::BTreeMap, hint, rc::Rc};
+
+/// How many Lox calls may be active at once before a "Stack overflow" error.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 256;
+
+/// Native stack the tree-walker may use before a "Stack overflow" error, half of a default
+/// 8 MiB main thread so the host's frames and the last nodes evaluated still fit.
+pub const DEFAULT_MAX_STACK: usize = 4 * 1024 * 1024;
+
+/// Which engine runs the resolved statements.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Backend {
+    /// Walks the AST, its objects live on a `Heap` collected by mark-sweep.
+    #[default]
+    TreeWalker,
+    /// Compiles to bytecode for a stack vm, its objects are reference counted and never
+    /// collected, so cycles between them leak.
+    Vm,
+}
 
+/// How a statement finished, `Return` unwinds up to the enclosing function call, `Break`
+/// and `Continue` up to the enclosing loop.
 #[derive(Debug, Clone)]
+pub enum Completion {
+    Normal,
+    Return(EnvironmentValue),
+    Break,
+    Continue,
+}
+
+#[derive(Debug)]
 pub struct Interpreter {
+    pub backend: Backend,
+    pub vm: Vm,
     pub envs: EnvironmentList,
-    statements: Rc<Vec<Stmt>>,
-    scope_record: Rc<RefCell<BTreeMap<usize, usize>>>,
-    pub return_val: EnvironmentValue,
+    pub interner: Interner,
+    scope_record: ScopeRecord,
+    /// Where the parser continues numbering nodes, so ids of REPL inputs never collide.
+    pub node_count: NodeId,
+    /// What starts a line comment in the sources run, `#` makes `//` floor division.
+    pub comments: CommentSyntax,
+    /// Functions being called and the line of their call site, innermost last.
+    call_stack: Vec<(Rc<String>, usize)>,
+    max_call_depth: usize,
+    /// Where the native stack was when the current run started, see `set_max_stack`.
+    stack_base: usize,
+    max_stack: usize,
+    budget: Budget,
     log_fn: Option<fn(String) -> ()>,
 }
 
 impl Interpreter {
-    pub fn new(
-        statements: Rc<Vec<Stmt>>,
-        scope_record: Rc<RefCell<BTreeMap<usize, usize>>>,
-        log_fn: Option<fn(String) -> ()>,
-    ) -> Self {
-        Interpreter {
+    pub fn new(log_fn: Option<fn(String) -> ()>) -> Self {
+        Interpreter::with_backend(log_fn, Backend::TreeWalker)
+    }
+
+    pub fn with_backend(log_fn: Option<fn(String) -> ()>, backend: Backend) -> Self {
+        let mut interpreter = Interpreter {
+            backend,
+            vm: Vm::new(log_fn),
             envs: EnvironmentList::new(),
-            statements,
-            scope_record,
-            return_val: EnvironmentValue::None,
+            interner: Interner::new(),
+            scope_record: ScopeRecord::default(),
+            node_count: 0,
+            comments: CommentSyntax::default(),
+            call_stack: Vec::new(),
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            stack_base: 0,
+            max_stack: DEFAULT_MAX_STACK,
+            budget: Budget::default(),
             log_fn,
-        }
+        };
+        define_native(&mut interpreter);
+        interpreter
     }
 
-    pub fn interpret(&mut self) {
-        for stmt in self.statements.clone().iter() {
-            if let Err(err) = self.evaluate_statement_item(stmt) {
-                let error = format!(
-                    "{} in line {} column {} \n",
-                    err.message, err.line, err.column
-                );
-                panic!("\n\n******\nOops! interpret errors:\n{}******\n\n", error);
-            }
+    /// Registers a host function as a global, an existing native with the same name is replaced.
+    pub fn define_native<F>(&mut self, name: &str, arity: usize, function: F)
+    where
+        F: Fn(&[EnvironmentValue]) -> Result<EnvironmentValue, String> + 'static,
+    {
+        let function = move |_: Option<&mut Heap>, args: &[EnvironmentValue]| function(args);
+        let native = LoxNativeFunction::new(name, arity, Rc::new(function));
+        let value = EnvironmentValue::LoxNativeFunction(Rc::new(native));
+        let symbol = self.interner.intern(name);
+        self.vm.define_native(symbol, name, value.clone());
+        self.envs.define_native(symbol, value);
+    }
+
+    /// How many Lox calls may nest before a "Stack overflow" error, on either backend.
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.max_call_depth = depth;
+        self.vm.set_max_call_depth(depth);
+    }
+
+    /// The tree-walker nests on the native stack for every call, block and subexpression,
+    /// and stops with a "Stack overflow" error once a run has used `bytes` of it. Leave room
+    /// on the thread for the frames of the host and for the few nodes evaluated past the limit.
+    pub fn set_max_stack(&mut self, bytes: usize) {
+        self.max_stack = bytes;
+    }
+
+    pub fn set_limits(&mut self, limits: ExecutionLimits) {
+        self.vm.set_limits(limits.clone());
+        self.budget = Budget::new(limits);
+    }
+
+    /// Runs resolved statements against the globals left by previous runs.
+    pub fn interpret(
+        &mut self,
+        statements: &[Stmt],
+        scope_record: &ScopeRecord,
+    ) -> Result<(), Error> {
+        self.scope_record.extend(scope_record);
+        self.budget.start();
+        self.envs.reset();
+        self.stack_base = stack_position();
+
+        for stmt in statements.iter() {
+            self.evaluate_statement_item(stmt)?;
         }
+        Ok(())
+    }
+
+    pub fn evaluate(&mut self, expr: &Expr) -> Result<EnvironmentValue, Error> {
+        self.stack_base = stack_position();
+        self.evaluate_expression_item(expr)
+    }
+
+    /// How `print` shows `value`, which either backend may have produced.
+    pub fn display(&self, value: &EnvironmentValue) -> String {
+        value.display(Some(&self.envs.heap))
     }
 
     fn evaluate_expression_item(&mut self, expr: &Expr) -> Result<EnvironmentValue, Error> {
+        if self.stack_exhausted() {
+            if let Some(token) = expr.token() {
+                return Err(stack_overflow(token));
+            }
+        }
+
         match expr {
             Expr::Binary(expr_binary) => self.visit_binary_expr(expr_binary),
             Expr::Logical(expr_logical) => self.visit_logical_expr(expr_logical),
@@ -58,10 +170,20 @@ impl Interpreter {
             Expr::Set(expr_set) => self.visit_set_expr(expr_set),
             Expr::This(expr_this) => self.visit_this_expr(expr_this),
             Expr::Super(expr_super) => self.visit_super_expr(expr_super),
+            Expr::List(expr_list) => self.visit_list_expr(expr_list),
+            Expr::Map(expr_map) => self.visit_map_expr(expr_map),
+            Expr::Index(expr_index) => self.visit_index_expr(expr_index),
+            Expr::IndexSet(expr_index_set) => self.visit_index_set_expr(expr_index_set),
         }
     }
 
-    fn evaluate_statement_item(&mut self, stmt: &Stmt) -> Result<(), Error> {
+    fn evaluate_statement_item(&mut self, stmt: &Stmt) -> Result<Completion, Error> {
+        if self.stack_exhausted() {
+            if let Some(token) = stmt.token() {
+                return Err(stack_overflow(token));
+            }
+        }
+
         match stmt {
             Stmt::Function(stmt_function) => self.visit_function_stmt(stmt_function),
             Stmt::If(stmt_if) => self.visit_if_stmt(stmt_if),
@@ -70,130 +192,139 @@ impl Interpreter {
             Stmt::While(stmt_while) => self.visit_while_stmt(stmt_while),
             Stmt::For(stmt_for) => self.visit_for_stmt(stmt_for),
             Stmt::Var(stmt_var) => self.visit_var_stmt(stmt_var),
-            Stmt::Block(stmt_block) => self.visit_block_stmt(stmt_block, None),
+            Stmt::Block(stmt_block) => self.visit_block_stmt(stmt_block),
             Stmt::Return(stmt_return) => self.visit_return_stmt(stmt_return),
+            Stmt::Break(_) => Ok(Completion::Break),
+            Stmt::Continue(_) => Ok(Completion::Continue),
             Stmt::Class(stmt_class) => self.visit_class_stmt(stmt_class),
         }
     }
 
-    fn visit_function_stmt(&mut self, stmt: &Rc<FunctionStatement>) -> Result<(), Error> {
-        let lox_function = LoxFunction::new(stmt.clone(), self.envs.env_pos, false);
+    fn visit_function_stmt(&mut self, stmt: &Rc<FunctionStatement>) -> Result<Completion, Error> {
+        let lox_function = LoxFunction::new(stmt.clone(), self.envs.current(), false);
+        let lox_function = self.envs.alloc(lox_function);
         self.envs.define(
-            ScopeAnalyst::get_scope_key_name(&stmt.name.lexeme),
-            EnvironmentValue::LoxFunction(Rc::new(RefCell::new(lox_function))),
-        )?;
-        Ok(())
+            stmt.name.symbol,
+            EnvironmentValue::LoxFunction(lox_function),
+        );
+        Ok(Completion::Normal)
     }
 
-    fn visit_if_stmt(&mut self, stmt: &IfStatement) -> Result<(), Error> {
+    fn visit_if_stmt(&mut self, stmt: &IfStatement) -> Result<Completion, Error> {
         let value = self.evaluate_expression_item(&stmt.condition)?;
 
         if value.is_truthy() {
-            self.evaluate_statement_item(&stmt.then_branch)?;
-        } else {
-            if let Some(else_branch) = &stmt.else_branch {
-                self.evaluate_statement_item(else_branch)?;
-            }
+            return self.evaluate_statement_item(&stmt.then_branch);
+        } else if let Some(else_branch) = &stmt.else_branch {
+            return self.evaluate_statement_item(else_branch);
         }
 
-        Ok(())
+        Ok(Completion::Normal)
     }
 
-    fn visit_expression_stmt(&mut self, stmt: &ExpressionStatement) -> Result<(), Error> {
+    fn visit_expression_stmt(&mut self, stmt: &ExpressionStatement) -> Result<Completion, Error> {
         self.evaluate_expression_item(&stmt.expression)?;
-        Ok(())
+        Ok(Completion::Normal)
     }
 
-    fn visit_print_stmt(&mut self, stmt: &PrintStatement) -> Result<(), Error> {
+    fn visit_print_stmt(&mut self, stmt: &PrintStatement) -> Result<Completion, Error> {
         let val = self.evaluate_expression_item(&stmt.expression)?;
 
         if self.log_fn.is_none() {
-            println!("{}", val);
+            println!("{}", self.display(&val));
         } else {
-            self.log_fn.unwrap()(format!("{}", val));
+            self.log_fn.unwrap()(self.display(&val));
         }
-        Ok(())
+        Ok(Completion::Normal)
     }
 
-    fn visit_while_stmt(&mut self, stmt: &WhileStatement) -> Result<(), Error> {
+    fn visit_while_stmt(&mut self, stmt: &WhileStatement) -> Result<Completion, Error> {
         while self.evaluate_expression_item(&stmt.condition)?.is_truthy() {
-            self.evaluate_statement_item(&stmt.body)?;
+            match self.evaluate_statement_item(&stmt.body)? {
+                Completion::Normal | Completion::Continue => {}
+                Completion::Break => break,
+                completion => return Ok(completion),
+            }
+            self.step(&stmt.keyword)?;
         }
 
-        Ok(())
+        Ok(Completion::Normal)
     }
 
-    fn visit_for_stmt(&mut self, stmt: &ForStatement) -> Result<(), Error> {
-        self.envs.next(None);
+    fn visit_for_stmt(&mut self, stmt: &ForStatement) -> Result<Completion, Error> {
+        self.envs.begin_scope();
+        let result = self.execute_for(stmt);
+        self.envs.end_scope();
+        result
+    }
 
+    fn execute_for(&mut self, stmt: &ForStatement) -> Result<Completion, Error> {
         if let Some(initializer) = &stmt.initializer {
             self.evaluate_statement_item(initializer)?;
         }
 
-        if let Some(condition) = &stmt.condition {
-            let mut flag = self.evaluate_expression_item(condition)?.is_truthy();
-            while flag {
-                self.evaluate_statement_item(&stmt.body)?;
-
-                if let Some(updator) = &stmt.updator {
-                    self.evaluate_expression_item(updator)?;
-                    flag = self.evaluate_expression_item(condition)?.is_truthy();
+        // A missing condition is always true, a missing updater does nothing.
+        loop {
+            if let Some(condition) = &stmt.condition {
+                if !self.evaluate_expression_item(condition)?.is_truthy() {
+                    break;
                 }
             }
+
+            match self.evaluate_statement_item(&stmt.body)? {
+                Completion::Normal | Completion::Continue => {}
+                Completion::Break => break,
+                completion => return Ok(completion),
+            }
+            self.step(&stmt.keyword)?;
+
+            if let Some(updator) = &stmt.updator {
+                self.evaluate_expression_item(updator)?;
+            }
         }
-        self.envs.back();
-        Ok(())
+        Ok(Completion::Normal)
     }
 
-    fn visit_var_stmt(&mut self, stmt: &VarStatement) -> Result<(), Error> {
+    fn visit_var_stmt(&mut self, stmt: &VarStatement) -> Result<Completion, Error> {
         let mut value = EnvironmentValue::None;
         if let Some(initializer) = &stmt.initializer {
             value = self.evaluate_expression_item(initializer)?;
         }
-        self.envs.define(stmt.name.lexeme.as_ptr(), value)?;
-        Ok(())
+        self.envs.define(stmt.name.symbol, value);
+        Ok(Completion::Normal)
     }
 
-    pub fn visit_block_stmt(
-        &mut self,
-        stmt: &BlockStatement,
-        environment: Option<usize>,
-    ) -> Result<(), Error> {
-        let previous_env_pos = self.envs.env_pos;
-
-        if let Some(env_pos) = environment {
-            self.envs.go_to_env_by_pos(env_pos);
-        } else {
-            self.envs.next(None);
-        }
-
-        let stmts = &stmt.statements;
+    fn visit_block_stmt(&mut self, stmt: &BlockStatement) -> Result<Completion, Error> {
+        self.envs.begin_scope();
+        let result = self.execute_block(&stmt.statements);
+        self.envs.end_scope();
+        result
+    }
 
-        for statement in stmts.iter() {
-            if let Err(err) = self.evaluate_statement_item(statement) {
-                self.envs.back();
-                return Err(err);
+    /// Runs `statements` in the current environment until one of them completes abruptly.
+    pub fn execute_block(&mut self, statements: &[Stmt]) -> Result<Completion, Error> {
+        for statement in statements.iter() {
+            match self.evaluate_statement_item(statement)? {
+                Completion::Normal => {}
+                completion => return Ok(completion),
             }
         }
-
-        if environment.is_none() {
-            self.envs.back();
-        } else {
-            self.envs.go_to_env_by_pos(previous_env_pos);
-        }
-        Ok(())
+        Ok(Completion::Normal)
     }
 
-    fn visit_return_stmt(&mut self, stmt: &ReturnStatement) -> Result<(), Error> {
-        self.return_val = self.evaluate_expression_item(&stmt.value)?;
-        Ok(())
+    fn visit_return_stmt(&mut self, stmt: &ReturnStatement) -> Result<Completion, Error> {
+        let mut value = EnvironmentValue::None;
+        if let Some(return_value) = &stmt.value {
+            value = self.evaluate_expression_item(return_value)?;
+        }
+        Ok(Completion::Return(value))
     }
 
-    fn visit_class_stmt(&mut self, stmt: &Rc<ClassStatement>) -> Result<(), Error> {
-        self.envs
-            .define(stmt.name.lexeme.as_ptr(), EnvironmentValue::None)?;
+    fn visit_class_stmt(&mut self, stmt: &Rc<ClassStatement>) -> Result<Completion, Error> {
+        let class_env = self.envs.current();
+        let class_slot = self.envs.define(stmt.name.symbol, EnvironmentValue::None);
+        let roots = self.envs.roots_len();
 
-        let mut has_previous = false;
         let mut super_class = None;
 
         if let Some(superclass) = &stmt.superclass {
@@ -201,55 +332,48 @@ impl Interpreter {
 
             match superclass_value {
                 EnvironmentValue::LoxClass(superclass_value_lox_class) => {
-                    has_previous = true;
-                    self.envs.next(None);
-                    self.envs.define(
-                        SUPER_STRING.as_ptr(),
-                        EnvironmentValue::LoxClass(superclass_value_lox_class.clone()),
-                    )?;
-                    super_class = Some(superclass_value_lox_class.clone());
+                    self.envs.root(&superclass_value);
+                    self.envs.begin_scope();
+                    self.envs.define(Symbol::SUPER, superclass_value.clone());
+                    super_class = Some(superclass_value_lox_class);
                 }
                 _ => {
-                    return Err(Error {
-                        line: stmt.name.line,
-                        column: stmt.name.column,
-                        message: format!("Superclass must be a class at {}", &stmt.name.lexeme),
-                    })
+                    return Err(Error::at(
+                        &stmt.name,
+                        "E0307",
+                        format!("Superclass must be a class at {}", &stmt.name.lexeme),
+                    ))
                 }
             };
         }
 
-        let methods = stmt
-            .methods
-            .iter()
-            .map(|f_stmt| {
-                let is_init = *f_stmt.name.lexeme == INIT_STRING;
-                let method = Rc::new(RefCell::new(LoxFunction::new(
-                    f_stmt.clone(),
-                    self.envs.env_pos,
-                    is_init,
-                )));
-
-                if is_init {
-                    (INIT_STRING.as_ptr(), method)
-                } else {
-                    (f_stmt.name.lexeme.as_ptr(), method)
-                }
-            })
-            .collect::<BTreeMap<_, _>>();
+        let mut methods = BTreeMap::new();
+        for f_stmt in stmt.methods.iter() {
+            let is_init = f_stmt.name.symbol == Symbol::INIT;
+            let method = LoxFunction::new(f_stmt.clone(), self.envs.current(), is_init);
+            let method = self.envs.alloc(method);
+            self.envs.root(&EnvironmentValue::LoxFunction(method));
+            methods.insert(f_stmt.name.symbol, method);
+        }
 
-        let lox_class = EnvironmentValue::LoxClass(Rc::new(RefCell::new(LoxClass::new(
+        let lox_class = self.envs.alloc(LoxClass::new(
             stmt.name.lexeme.clone(),
             super_class,
             methods,
-        ))));
+        ));
 
-        if has_previous {
-            self.envs.back();
+        if super_class.is_some() {
+            self.envs.end_scope();
         }
-
-        self.envs.assign(&stmt.name, lox_class)?;
-        Ok(())
+        self.envs.unroot_to(roots);
+
+        self.envs.assign_at(
+            stmt.name.symbol,
+            class_env,
+            class_slot,
+            EnvironmentValue::LoxClass(lox_class),
+        );
+        Ok(Completion::Normal)
     }
 
     fn visit_binary_expr(
@@ -261,22 +385,28 @@ impl Interpreter {
 
         match expr.operator.token_type {
             TokensType::Plus => {
-                if let Ok(value) = EnvironmentValue::add(&left, &right) {
+                if let Some(value) = EnvironmentValue::add(&left, &right) {
+                    self.budget
+                        .check_memory()
+                        .map_err(|(code, message)| Error::at(&expr.operator, code, message))?;
                     return Ok(value);
                 } else {
-                    Err(Error {
-                        line: expr.operator.line,
-                        column: expr.operator.column,
-                        message: format!(
+                    Err(Error::at(
+                        &expr.operator,
+                        "E0301",
+                        format!(
                             "Operands must be two numbers or two strings {}",
                             &expr.operator.lexeme
                         ),
-                    })
+                    ))
                 }
             }
             TokensType::Minus
             | TokensType::Slash
             | TokensType::Star
+            | TokensType::Percent
+            | TokensType::SlashSlash
+            | TokensType::StarStar
             | TokensType::Greater
             | TokensType::GreaterEqual
             | TokensType::Less
@@ -284,38 +414,36 @@ impl Interpreter {
                 return Ok(self.number_binary_calculate(&expr.operator, left, right)?)
             }
             TokensType::BangEqual => {
-                let result = EnvironmentValue::partial_eq(&left, &right);
-                if result.is_ok() {
-                    return Ok(result.unwrap());
+                if let Some(value) = EnvironmentValue::partial_eq(&left, &right) {
+                    return Ok(value);
                 }
-                Err(Error {
-                    line: expr.operator.line,
-                    column: expr.operator.column,
-                    message: format!(
+                Err(Error::at(
+                    &expr.operator,
+                    "E0302",
+                    format!(
                         r#""!=" and "==" operands only support number/string/boolean {}"#,
                         &expr.operator.lexeme
                     ),
-                })
+                ))
             }
             TokensType::EqualEqual => {
-                let result = EnvironmentValue::eq(&left, &right);
-                if result.is_ok() {
-                    return Ok(result.unwrap());
+                if let Some(value) = EnvironmentValue::eq(&left, &right) {
+                    return Ok(value);
                 }
-                Err(Error {
-                    line: expr.operator.line,
-                    column: expr.operator.column,
-                    message: format!(
+                Err(Error::at(
+                    &expr.operator,
+                    "E0302",
+                    format!(
                         r#""!=" and "==" operands only support number/string/boolean {}"#,
                         &expr.operator.lexeme
                     ),
-                })
+                ))
             }
-            _ => Err(Error {
-                line: expr.operator.line,
-                column: expr.operator.column,
-                message: String::from("Should not happen"),
-            }),
+            _ => Err(Error::at(
+                &expr.operator,
+                "E0399",
+                String::from("Should not happen"),
+            )),
         }
     }
 
@@ -329,29 +457,44 @@ impl Interpreter {
         let right_is_number = right.is_number();
 
         if !left_is_number || !right_is_number {
-            return Err(Error {
-                line: operator.line,
-                column: operator.column,
-                message: format!(
+            return Err(Error::at(
+                operator,
+                "E0301",
+                format!(
                     "Operands must be two numbers or two strings {}",
                     &operator.lexeme
                 ),
-            });
+            ));
+        }
+
+        let divides = matches!(
+            operator.token_type,
+            TokensType::Percent | TokensType::SlashSlash
+        );
+        if divides && right.is_zero() {
+            return Err(Error::at(
+                operator,
+                DIVISION_BY_ZERO,
+                format!("Division by zero at {}", &operator.lexeme),
+            ));
         }
 
         match operator.token_type {
             TokensType::Minus => Ok(EnvironmentValue::sub(&left, &right).unwrap()),
             TokensType::Slash => Ok(EnvironmentValue::div(&left, &right).unwrap()),
             TokensType::Star => Ok(EnvironmentValue::mul(&left, &right).unwrap()),
+            TokensType::Percent => Ok(EnvironmentValue::rem(&left, &right).unwrap()),
+            TokensType::SlashSlash => Ok(EnvironmentValue::floor_div(&left, &right).unwrap()),
+            TokensType::StarStar => Ok(EnvironmentValue::pow(&left, &right).unwrap()),
             TokensType::Greater => Ok(EnvironmentValue::gt(&left, &right).unwrap()),
             TokensType::GreaterEqual => Ok(EnvironmentValue::ge(&left, &right).unwrap()),
             TokensType::Less => Ok(EnvironmentValue::lt(&left, &right).unwrap()),
             TokensType::LessEqual => Ok(EnvironmentValue::le(&left, &right).unwrap()),
-            _ => Err(Error {
-                line: operator.line,
-                column: operator.column,
-                message: String::from("Should not happen"),
-            }),
+            _ => Err(Error::at(
+                operator,
+                "E0399",
+                String::from("Should not happen"),
+            )),
         }
     }
 
@@ -371,11 +514,11 @@ impl Interpreter {
                 }
                 return Ok(EnvironmentValue::Bool(false));
             }
-            _ => Err(Error {
-                line: expr.operator.line,
-                column: expr.operator.column,
-                message: String::from("Should not happen"),
-            }),
+            _ => Err(Error::at(
+                &expr.operator,
+                "E0399",
+                String::from("Should not happen"),
+            )),
         }
     }
 
@@ -392,7 +535,9 @@ impl Interpreter {
             match val {
                 ValueType::Bool(bool_val) => Ok(EnvironmentValue::Bool(*bool_val)),
                 ValueType::Number(number_val) => Ok(EnvironmentValue::Number(*number_val)),
-                ValueType::String(string_val) => Ok(EnvironmentValue::String(string_val.clone())),
+                ValueType::String(string_val) => {
+                    Ok(EnvironmentValue::String(string_val.clone().into()))
+                }
             }
         } else {
             Ok(EnvironmentValue::None)
@@ -406,20 +551,20 @@ impl Interpreter {
             TokensType::Minus => {
                 let right_is_number = right.is_number();
                 if !right_is_number {
-                    return Err(Error {
-                        line: expr.operator.line,
-                        column: expr.operator.column,
-                        message: format!("Operand must be a number at {}", &expr.operator.lexeme),
-                    });
+                    return Err(Error::at(
+                        &expr.operator,
+                        "E0309",
+                        format!("Operand must be a number at {}", &expr.operator.lexeme),
+                    ));
                 }
                 return Ok(EnvironmentValue::neg(&right).unwrap());
             }
             TokensType::Bang => Ok(EnvironmentValue::Bool(!right.is_truthy())),
-            _ => Err(Error {
-                line: expr.operator.line,
-                column: expr.operator.column,
-                message: String::from("Should not happen"),
-            }),
+            _ => Err(Error::at(
+                &expr.operator,
+                "E0399",
+                String::from("Should not happen"),
+            )),
         }
     }
 
@@ -427,16 +572,10 @@ impl Interpreter {
         &mut self,
         expr: &Rc<VariableExpression>,
     ) -> Result<EnvironmentValue, Error> {
-        let add = get_rc_ref_address(expr.clone());
-
-        if !self.scope_record.borrow().contains_key(&add) {
-            return Ok(self.envs.global_get(&expr.name)?.clone());
+        match self.scope_record.get(expr.id) {
+            Some(scope_slot) => Ok(self.envs.get_by_distance(&expr.name, scope_slot)?.clone()),
+            None => Ok(self.envs.global_get(&expr.name)?.clone()),
         }
-
-        let borrow_env = self.scope_record.borrow();
-        let distance = borrow_env.get(&add).unwrap();
-        let value = Ok(self.envs.get_by_distance(&expr.name, *distance)?.clone());
-        value
     }
 
     fn visit_assignment_expr(
@@ -444,68 +583,169 @@ impl Interpreter {
         expr: &Rc<AssignmentExpression>,
     ) -> Result<EnvironmentValue, Error> {
         let value = self.evaluate_expression_item(&expr.value)?;
-        let add = get_rc_ref_address(expr.clone());
-
-        if !self.scope_record.borrow().contains_key(&add) {
-            self.envs.global_assign(&expr.name, value.clone())?;
-            return Ok(value);
+        match self.scope_record.get(expr.id) {
+            Some(scope_slot) => {
+                self.envs
+                    .assign_by_distance(&expr.name, scope_slot, value.clone())?
+            }
+            None => self.envs.global_assign(&expr.name, value.clone())?,
         }
-
-        let borrow_env = self.scope_record.borrow();
-        let distance = borrow_env.get(&add).unwrap();
-        self.envs
-            .assign_by_distance(&expr.name, *distance, value.clone())?;
         Ok(value)
     }
 
     fn visit_call_expr(&mut self, expr: &CallExpression) -> Result<EnvironmentValue, Error> {
-        let mut callee = self.evaluate_expression_item(&expr.callee)?;
-        let args = &expr
-            .args
-            .iter()
-            .map(|arg| self.evaluate_expression_item(arg))
-            .collect::<Vec<_>>();
+        let callee = self.evaluate_expression_item(&expr.callee)?;
+        let roots = self.envs.roots_len();
+        self.envs.root(&callee);
+
+        let result = self.call_value(callee, expr);
+        self.envs.unroot_to(roots);
+        result
+    }
+
+    fn call_value(
+        &mut self,
+        callee: EnvironmentValue,
+        expr: &CallExpression,
+    ) -> Result<EnvironmentValue, Error> {
+        let args = self.evaluate_rooted(&expr.args)?;
 
         match callee {
-            EnvironmentValue::LoxClass(ref mut lox_class) => {
-                if args.len() != lox_class.borrow().arity() {
-                    return Err(Error {
-                        line: expr.end_parenthese.line,
-                        column: expr.end_parenthese.column,
-                        message: format!(
+            EnvironmentValue::LoxClass(lox_class) => {
+                let arity = self.envs.heap.get(lox_class).arity(&self.envs.heap);
+                if args.len() != arity {
+                    return Err(Error::at(
+                        &expr.end_parenthese,
+                        "E0303",
+                        format!(
                             r#"Expect {} arguments but got {}, at ")""#,
-                            lox_class.borrow().arity().to_string(),
+                            arity.to_string(),
                             args.len().to_string()
                         ),
-                    });
+                    ));
                 }
-                return lox_class.borrow_mut().call(self, args);
+                let name = self.interner.name(Symbol::INIT).clone();
+                self.call_frame(name, &expr.end_parenthese, |interpreter| {
+                    LoxClass::call(lox_class, interpreter, args)
+                })
             }
-            EnvironmentValue::LoxFunction(ref mut lox_function) => {
-                if args.len() != lox_function.borrow().arity() {
-                    return Err(Error {
-                        line: expr.end_parenthese.line,
-                        column: expr.end_parenthese.column,
-                        message: format!(
+            EnvironmentValue::LoxFunction(lox_function) => {
+                let lox_function = self.envs.heap.get(lox_function).clone();
+                if args.len() != lox_function.arity() {
+                    return Err(Error::at(
+                        &expr.end_parenthese,
+                        "E0303",
+                        format!(
                             r#"Expect {} arguments but got {}, at ")""#,
-                            lox_function.borrow().arity().to_string(),
+                            lox_function.arity().to_string(),
                             args.len().to_string()
                         ),
-                    });
+                    ));
                 }
-                return lox_function.borrow_mut().call(self, args);
+                let name = lox_function.name();
+                self.call_frame(name, &expr.end_parenthese, |interpreter| {
+                    lox_function.call(interpreter, args)
+                })
+            }
+            EnvironmentValue::LoxNativeFunction(ref native) => {
+                if args.len() != native.arity() {
+                    return Err(Error::at(
+                        &expr.end_parenthese,
+                        "E0303",
+                        format!(
+                            r#"Expect {} arguments but got {}, at ")""#,
+                            native.arity(),
+                            args.len()
+                        ),
+                    ));
+                }
+                native
+                    .call(Some(&mut self.envs.heap), &args)
+                    .map_err(|message| {
+                        Error::at(
+                            &expr.end_parenthese,
+                            "E0308",
+                            format!("{} in native function {}", message, native.name()),
+                        )
+                    })
             }
             _ => {
-                return Err(Error {
-                    line: expr.end_parenthese.line,
-                    column: expr.end_parenthese.column,
-                    message: format!(
+                return Err(Error::at(
+                    &expr.end_parenthese,
+                    "E0304",
+                    format!(
                         "Can only call functions and classes at {}",
                         &expr.end_parenthese.lexeme
                     ),
-                })
+                ))
+            }
+        }
+    }
+
+    /// Evaluates `exprs` in order, rooting each value until the caller unroots them.
+    fn evaluate_rooted(&mut self, exprs: &[Expr]) -> Result<Vec<EnvironmentValue>, Error> {
+        let mut values = Vec::with_capacity(exprs.len());
+        for expr in exprs.iter() {
+            let value = self.evaluate_expression_item(expr)?;
+            self.envs.root(&value);
+            values.push(value);
+        }
+        Ok(values)
+    }
+
+    /// Runs `call` as a new call frame, an error raised inside it leaves with the
+    /// backtrace of where it was raised.
+    fn call_frame<F>(
+        &mut self,
+        name: Rc<String>,
+        paren: &Token,
+        call: F,
+    ) -> Result<EnvironmentValue, Error>
+    where
+        F: FnOnce(&mut Interpreter) -> Result<EnvironmentValue, Error>,
+    {
+        if self.call_stack.len() >= self.max_call_depth {
+            return Err(stack_overflow(paren));
+        }
+        self.step(paren)?;
+        self.call_stack.push((name, paren.line));
+        let result = call(self).map_err(|mut err| {
+            if err.backtrace.is_empty() {
+                err.backtrace = self.backtrace(err.line);
             }
+            err
+        });
+        self.call_stack.pop();
+        result
+    }
+
+    /// More of the native stack is in use than `max_stack` allows, a literal or an expression
+    /// statement past the limit is let through since it nests at most one node deeper.
+    #[inline]
+    fn stack_exhausted(&self) -> bool {
+        self.stack_base.saturating_sub(stack_position()) > self.max_stack
+    }
+
+    fn step(&mut self, token: &Token) -> Result<(), Error> {
+        self.budget
+            .step()
+            .map_err(|(code, message)| Error::at(token, code, message))
+    }
+
+    fn backtrace(&self, mut line: usize) -> Vec<TraceFrame> {
+        let mut backtrace = Vec::with_capacity(self.call_stack.len() + 1);
+        for (name, call_line) in self.call_stack.iter().rev() {
+            backtrace.push(TraceFrame {
+                name: name.clone(),
+                line,
+            });
+            line = *call_line;
         }
+        backtrace.push(TraceFrame {
+            name: Rc::new(String::from("script")),
+            line,
+        });
+        backtrace
     }
 
     fn visit_get_expr(&mut self, expr: &GetExpression) -> Result<EnvironmentValue, Error> {
@@ -513,15 +753,33 @@ impl Interpreter {
 
         match obj {
             EnvironmentValue::LoxInstance(lox_instance) => {
-                let lox_instance = lox_instance.clone();
-                let lox_instance = lox_instance.borrow_mut(); //TODO:实现的有没有问题？
-                return lox_instance.get(&expr.name, self);
+                let roots = self.envs.roots_len();
+                self.envs.root(&obj);
+                let value = LoxInstance::get(lox_instance, &expr.name, self);
+                self.envs.unroot_to(roots);
+                value
+            }
+            EnvironmentValue::List(_) => {
+                LoxList::method(&obj, &expr.name.lexeme).ok_or_else(|| {
+                    Error::at(
+                        &expr.name,
+                        "E0306",
+                        format!("Undefined property {}", &expr.name.lexeme),
+                    )
+                })
             }
-            _ => Err(Error {
-                line: expr.name.line,
-                column: expr.name.column,
-                message: format!("Only instances have properties at {}", &expr.name.lexeme),
+            EnvironmentValue::Map(_) => LoxMap::method(&obj, &expr.name.lexeme).ok_or_else(|| {
+                Error::at(
+                    &expr.name,
+                    "E0306",
+                    format!("Undefined property {}", &expr.name.lexeme),
+                )
             }),
+            _ => Err(Error::at(
+                &expr.name,
+                "E0305",
+                format!("Only instances have properties at {}", &expr.name.lexeme),
+            )),
         }
     }
 
@@ -530,59 +788,186 @@ impl Interpreter {
 
         match obj {
             EnvironmentValue::LoxInstance(lox_instance) => {
-                let value = self.evaluate_expression_item(&expr.value)?;
-                let mut lox_instance = lox_instance.borrow_mut();
-                lox_instance.set(&expr.name, value.clone());
+                let roots = self.envs.roots_len();
+                self.envs.root(&obj);
+                let value = self.evaluate_expression_item(&expr.value);
+                self.envs.unroot_to(roots);
+                let value = value?;
+
+                self.envs
+                    .heap
+                    .get_mut(lox_instance)
+                    .set(&expr.name, value.clone());
                 return Ok(value);
             }
-            _ => Err(Error {
-                line: expr.name.line,
-                column: expr.name.column,
-                message: format!("Only instances have properties at {}", &expr.name.lexeme),
-            }),
+            _ => Err(Error::at(
+                &expr.name,
+                "E0305",
+                format!("Only instances have properties at {}", &expr.name.lexeme),
+            )),
+        }
+    }
+
+    fn visit_list_expr(&mut self, expr: &ListExpression) -> Result<EnvironmentValue, Error> {
+        let roots = self.envs.roots_len();
+        let elements = self.evaluate_rooted(&expr.elements);
+        self.envs.unroot_to(roots);
+
+        let list = LoxList::new(elements?);
+        self.budget
+            .check_memory()
+            .map_err(|(code, message)| Error::at(&expr.end_bracket, code, message))?;
+        Ok(EnvironmentValue::List(self.envs.alloc(list)))
+    }
+
+    fn visit_map_expr(&mut self, expr: &MapExpression) -> Result<EnvironmentValue, Error> {
+        let exprs = expr
+            .entries
+            .iter()
+            .flat_map(|(key, value)| [key.clone(), value.clone()])
+            .collect::<Vec<_>>();
+        let roots = self.envs.roots_len();
+        let values = self.evaluate_rooted(&exprs);
+        self.envs.unroot_to(roots);
+
+        let mut values = values?.into_iter();
+        let mut entries = Vec::with_capacity(expr.entries.len());
+        while let (Some(key), Some(value)) = (values.next(), values.next()) {
+            entries.push((key, value));
+        }
+        let map = LoxMap::new(entries)
+            .map_err(|(code, message)| Error::at(&expr.end_brace, code, message))?;
+        self.budget
+            .check_memory()
+            .map_err(|(code, message)| Error::at(&expr.end_brace, code, message))?;
+        Ok(EnvironmentValue::Map(self.envs.alloc(map)))
+    }
+
+    fn visit_index_expr(&mut self, expr: &IndexExpression) -> Result<EnvironmentValue, Error> {
+        let obj = self.evaluate_expression_item(&expr.object)?;
+        let roots = self.envs.roots_len();
+        self.envs.root(&obj);
+        let index = self.evaluate_expression_item(&expr.index);
+        self.envs.unroot_to(roots);
+        let index = index?;
+
+        match obj {
+            EnvironmentValue::List(list) => self
+                .envs
+                .heap
+                .get(list)
+                .get(&index)
+                .map_err(|(code, message)| Error::at(&expr.end_bracket, code, message)),
+            EnvironmentValue::Map(map) => self
+                .envs
+                .heap
+                .get(map)
+                .get(&index)
+                .map_err(|(code, message)| Error::at(&expr.end_bracket, code, message)),
+            _ => Err(Error::at(
+                &expr.end_bracket,
+                NOT_INDEXABLE,
+                String::from("Only lists and maps can be indexed"),
+            )),
+        }
+    }
+
+    fn visit_index_set_expr(
+        &mut self,
+        expr: &IndexSetExpression,
+    ) -> Result<EnvironmentValue, Error> {
+        let obj = self.evaluate_expression_item(&expr.object)?;
+        let roots = self.envs.roots_len();
+        self.envs.root(&obj);
+        let values = self.evaluate_rooted(&[expr.index.clone(), expr.value.clone()]);
+        self.envs.unroot_to(roots);
+        let values = values?;
+
+        match obj {
+            EnvironmentValue::List(list) => {
+                self.envs
+                    .heap
+                    .get_mut(list)
+                    .set(&values[0], values[1].clone())
+                    .map_err(|(code, message)| Error::at(&expr.end_bracket, code, message))?;
+                Ok(values[1].clone())
+            }
+            EnvironmentValue::Map(map) => {
+                self.envs
+                    .heap
+                    .get_mut(map)
+                    .set(&values[0], values[1].clone())
+                    .map_err(|(code, message)| Error::at(&expr.end_bracket, code, message))?;
+                self.budget
+                    .check_memory()
+                    .map_err(|(code, message)| Error::at(&expr.end_bracket, code, message))?;
+                Ok(values[1].clone())
+            }
+            _ => Err(Error::at(
+                &expr.end_bracket,
+                NOT_INDEXABLE,
+                String::from("Only lists and maps can be indexed"),
+            )),
         }
     }
 
     fn visit_this_expr(&mut self, expr: &Rc<ThisExpression>) -> Result<EnvironmentValue, Error> {
-        let add = get_rc_ref_address(expr.clone());
-        let distance = self.scope_record.borrow();
-        let distance = distance.get(&add).unwrap();
-        let value = Ok(self.envs.get_by_distance(&expr.keyword, *distance)?.clone());
-        value
+        let scope_slot = self.scope_record.get(expr.id).unwrap();
+        Ok(self
+            .envs
+            .get_by_distance(&expr.keyword, scope_slot)?
+            .clone())
     }
 
     fn visit_super_expr(&mut self, expr: &Rc<SuperExpression>) -> Result<EnvironmentValue, Error> {
-        let add = get_rc_ref_address(expr.clone());
-        let superclass;
-        let distance;
-        {
-            let borrow_scope_record = self.scope_record.borrow();
-            distance = borrow_scope_record.get(&add).unwrap().clone();
-            superclass = self.envs.get_by_distance(&expr.keyword, distance)?.clone();
-        }
+        let scope_slot = self.scope_record.get(expr.id).unwrap();
+        let superclass = self
+            .envs
+            .get_by_distance(&expr.keyword, scope_slot)?
+            .clone();
 
         match superclass {
             EnvironmentValue::LoxClass(superclass) => {
+                // "this" is the only slot of the scope right inside the "super" one.
+                let this_slot = ScopeSlot {
+                    depth: scope_slot.depth - 1,
+                    slot: 0,
+                };
                 let obj = self
                     .envs
-                    .get_by_distance_default(&THIS_STRING.as_ptr(), distance)
+                    .get_by_distance_default(this_slot)
                     .unwrap()
                     .clone();
 
-                let method = superclass
-                    .borrow()
-                    .find_method(&expr.method.lexeme.as_ptr());
+                let method = self
+                    .envs
+                    .heap
+                    .get(superclass)
+                    .find_method(&self.envs.heap, expr.method.symbol);
 
                 if let Some(method) = method {
-                    return Ok(method.clone().borrow_mut().bind(obj.clone(), self)?);
+                    let method = self.envs.heap.get(method).clone();
+                    return Ok(method.bind(obj, self));
                 }
             }
             _ => {}
         }
-        Err(Error {
-            line: expr.keyword.line,
-            column: expr.keyword.column,
-            message: format!("Undefined property {}", expr.method.lexeme),
-        })
+        Err(Error::at(
+            &expr.keyword,
+            "E0306",
+            format!("Undefined property {}", expr.method.lexeme),
+        ))
     }
 }
+
+/// Address of a local in the caller's frame, the native stack grows down on every target
+/// this builds for.
+#[inline(always)]
+fn stack_position() -> usize {
+    let marker = 0u8;
+    hint::black_box(&marker) as *const u8 as usize
+}
+
+fn stack_overflow(token: &Token) -> Error {
+    Error::at(token, "E0310", String::from("Stack overflow"))
+}
@@ -1,24 +1,25 @@
 use crate::environment::environment_value::EnvironmentValue;
+use crate::environment::heap::{Gc, Heap, Trace, Tracer};
 use crate::interpreter::interpreter::Interpreter;
 use crate::interpreter::lox_function::*;
 use crate::interpreter::lox_instance::*;
+use crate::scanner::interner::Symbol;
 use crate::scanner::scanner::Error;
-use crate::semantic::scope_analyst::*;
 
-use std::{cell::RefCell, collections::BTreeMap, rc::Rc};
+use std::{collections::BTreeMap, rc::Rc};
 
 #[derive(Debug, Clone)]
 pub struct LoxClass {
     name: Rc<String>,
-    superclass: Option<Rc<RefCell<LoxClass>>>,
-    methods: BTreeMap<*const u8, Rc<RefCell<LoxFunction>>>,
+    superclass: Option<Gc<LoxClass>>,
+    methods: BTreeMap<Symbol, Gc<LoxFunction>>,
 }
 
 impl LoxClass {
     pub fn new(
         name: Rc<String>,
-        superclass: Option<Rc<RefCell<LoxClass>>>,
-        methods: BTreeMap<*const u8, Rc<RefCell<LoxFunction>>>,
+        superclass: Option<Gc<LoxClass>>,
+        methods: BTreeMap<Symbol, Gc<LoxFunction>>,
     ) -> Self {
         LoxClass {
             name,
@@ -27,48 +28,61 @@ impl LoxClass {
         }
     }
 
-    pub fn arity(&self) -> usize {
-        if let Some(initializer) = self.methods.get(&INIT_STRING.as_ptr()) {
-            return initializer.borrow().arity();
+    pub fn arity(&self, heap: &Heap) -> usize {
+        if let Some(initializer) = self.methods.get(&Symbol::INIT) {
+            return heap.get(*initializer).arity();
         }
         0
     }
 
-    pub fn find_method(&self, name: &*const u8) -> Option<Rc<RefCell<LoxFunction>>> {
-        if let Some(method) = self.methods.get(name) {
-            return Some(method.clone());
+    pub fn find_method(&self, heap: &Heap, name: Symbol) -> Option<Gc<LoxFunction>> {
+        if let Some(method) = self.methods.get(&name) {
+            return Some(*method);
         }
 
-        if let Some(superclass) = self.superclass.clone() {
-            return superclass.clone().borrow().find_method(name);
+        if let Some(superclass) = self.superclass {
+            return heap.get(superclass).find_method(heap, name);
         }
 
-        return None;
+        None
     }
 
     pub fn call(
-        &mut self,
+        class: Gc<LoxClass>,
         interpreter: &mut Interpreter,
-        args: &Vec<Result<EnvironmentValue, Error>>,
+        args: Vec<EnvironmentValue>,
     ) -> Result<EnvironmentValue, Error> {
-        let instance = LoxInstance::new(Rc::new(RefCell::new(self.clone())));
-        let instance = Rc::new(RefCell::new(instance));
+        let initializer = interpreter
+            .envs
+            .heap
+            .get(class)
+            .methods
+            .get(&Symbol::INIT)
+            .copied();
+        let instance = interpreter.envs.alloc(LoxInstance::new(class));
+        let instance = EnvironmentValue::LoxInstance(instance);
 
-        if let Some(initializer) = self.methods.get(&INIT_STRING.as_ptr()) {
-            let mut borrow_function = initializer.borrow_mut();
+        if let Some(initializer) = initializer {
+            let initializer = interpreter.envs.heap.get(initializer).clone();
 
-            let value = borrow_function
-                .bind(EnvironmentValue::LoxInstance(instance.clone()), interpreter)?;
-
-            match value {
-                EnvironmentValue::LoxFunction(lox_function) => {
-                    let borrow_lox_function = lox_function.clone();
-                    let mut borrow_lox_function = borrow_lox_function.borrow_mut();
-                    borrow_lox_function.call(interpreter, args)?;
-                }
-                _ => {}
+            if let EnvironmentValue::LoxFunction(bound) =
+                initializer.bind(instance.clone(), interpreter)
+            {
+                let bound = interpreter.envs.heap.get(bound).clone();
+                bound.call(interpreter, args)?;
             }
         }
-        return Ok(EnvironmentValue::LoxInstance(instance.clone()));
+        Ok(instance)
+    }
+}
+
+impl Trace for LoxClass {
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Some(superclass) = self.superclass {
+            tracer.mark(superclass);
+        }
+        for method in self.methods.values() {
+            tracer.mark(*method);
+        }
     }
 }
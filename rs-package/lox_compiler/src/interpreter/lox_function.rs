@@ -1,19 +1,24 @@
+use crate::environment::environment::Environment;
 use crate::environment::environment_value::EnvironmentValue;
-use crate::interpreter::interpreter::Interpreter;
+use crate::environment::heap::{Gc, Trace, Tracer};
+use crate::interpreter::interpreter::{Completion, Interpreter};
 use crate::parser::statement::FunctionStatement;
 use crate::scanner::scanner::Error;
-use crate::semantic::scope_analyst::*;
-use std::{cell::RefCell, rc::Rc};
+use std::rc::Rc;
 
 #[derive(Debug, Clone)]
 pub struct LoxFunction {
     declaration: Rc<FunctionStatement>,
-    closure: usize,
+    closure: Option<Gc<Environment>>,
     is_initializer: bool,
 }
 
 impl LoxFunction {
-    pub fn new(declaration: Rc<FunctionStatement>, closure: usize, is_initializer: bool) -> Self {
+    pub fn new(
+        declaration: Rc<FunctionStatement>,
+        closure: Option<Gc<Environment>>,
+        is_initializer: bool,
+    ) -> Self {
         LoxFunction {
             declaration,
             closure,
@@ -25,57 +30,56 @@ impl LoxFunction {
         self.declaration.params.len()
     }
 
+    pub fn name(&self) -> Rc<String> {
+        self.declaration.name.lexeme.clone()
+    }
+
+    /// Runs the body in a new scope holding `args` as the parameters. Called on a copy taken
+    /// off the heap, the function object itself may be collected while its body runs.
     pub fn call(
-        &mut self,
+        &self,
         interpreter: &mut Interpreter,
-        args: &Vec<Result<EnvironmentValue, Error>>,
+        args: Vec<EnvironmentValue>,
     ) -> Result<EnvironmentValue, Error> {
-        let iter = &self.declaration.clone().params;
+        interpreter.envs.enter(self.closure, args);
+        let completion = interpreter.execute_block(&self.declaration.body.statements);
+        interpreter.envs.leave();
+        let completion = completion?;
 
-        let env_pos = interpreter.envs.env_pos;
-        interpreter.envs.next(Some(self.closure));
-        for (pos, decs) in iter.iter().enumerate() {
-            let arg = args[pos].clone().unwrap();
-            let name_ptr = ScopeAnalyst::get_scope_key_name(&decs.lexeme);
-            interpreter.envs.define(name_ptr, arg)?;
+        if self.is_initializer {
+            // "this" is the only slot of the scope `bind` created.
+            if let Some(closure) = self.closure {
+                return Ok(interpreter.envs.get_at(closure, 0).clone());
+            }
         }
 
-        let block_previous_env_pos = interpreter.envs.env_pos;
-
-        // interpreter.envs.back_without_clear();
-        interpreter.envs.env_pos = env_pos;
-
-        interpreter.visit_block_stmt(&self.declaration.body, Some(block_previous_env_pos))?;
-
-        let return_val = interpreter.return_val.clone();
-
-        if self.is_initializer {
-            let value = interpreter
-                .envs
-                .get_with_pos(&THIS_STRING.as_ptr(), self.closure)?
-                .clone();
-            return Ok(value.clone());
+        match completion {
+            Completion::Return(value) => Ok(value),
+            // The parser keeps "break" and "continue" inside loops, which never let them out.
+            Completion::Normal | Completion::Break | Completion::Continue => {
+                Ok(EnvironmentValue::None)
+            }
         }
-        Ok(return_val)
     }
 
     pub fn bind(
-        &mut self,
+        &self,
         instance: EnvironmentValue,
         interpreter: &mut Interpreter,
-    ) -> Result<EnvironmentValue, Error> {
-        let from_env_pos = interpreter.envs.next(Some(self.closure));
-        interpreter.envs.define(THIS_STRING.as_ptr(), instance)?;
-
-        let lox_function = LoxFunction::new(
-            self.declaration.clone(),
-            interpreter.envs.env_pos,
-            self.is_initializer,
-        );
-        interpreter.envs.go_to_env_by_pos(from_env_pos);
+    ) -> EnvironmentValue {
+        let closure = interpreter
+            .envs
+            .alloc(Environment::new(self.closure, vec![instance]));
+        let lox_function =
+            LoxFunction::new(self.declaration.clone(), Some(closure), self.is_initializer);
+        EnvironmentValue::LoxFunction(interpreter.envs.alloc(lox_function))
+    }
+}
 
-        return Ok(EnvironmentValue::LoxFunction(Rc::new(RefCell::new(
-            lox_function,
-        ))));
+impl Trace for LoxFunction {
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Some(closure) = self.closure {
+            tracer.mark(closure);
+        }
     }
 }
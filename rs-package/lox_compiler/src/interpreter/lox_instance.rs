@@ -1,52 +1,65 @@
 use crate::environment::environment_value::EnvironmentValue;
+use crate::environment::heap::{Gc, Trace, Tracer};
 use crate::interpreter::interpreter::Interpreter;
+use crate::memory::Charge;
+use crate::scanner::interner::Symbol;
 use crate::scanner::{scanner::*, tokens::*};
 
 use super::lox_class::*;
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{collections::HashMap, mem};
 
 #[derive(Debug, Clone)]
 pub struct LoxInstance {
-    belong_class: Rc<RefCell<LoxClass>>,
-    fields: HashMap<*const u8, EnvironmentValue>,
+    belong_class: Gc<LoxClass>,
+    fields: HashMap<Symbol, EnvironmentValue>,
+    charge: Charge,
 }
 
 impl LoxInstance {
-    pub fn new(belong_class: Rc<RefCell<LoxClass>>) -> Self {
+    pub fn new(belong_class: Gc<LoxClass>) -> Self {
         LoxInstance {
             belong_class,
             fields: HashMap::new(),
+            charge: Charge::new(mem::size_of::<LoxInstance>()),
         }
     }
 
     pub fn get(
-        &self,
+        instance: Gc<LoxInstance>,
         name: &Token,
         interpreter: &mut Interpreter,
     ) -> Result<EnvironmentValue, Error> {
-        let key_ptr = name.lexeme.as_ptr();
-
-        if let Some(value) = self.fields.get(&key_ptr) {
+        let heap = &interpreter.envs.heap;
+        let this = heap.get(instance);
+        if let Some(value) = this.fields.get(&name.symbol) {
             return Ok(value.clone());
         }
 
-        if let Some(method) = self.belong_class.borrow().find_method(&key_ptr) {
-            let borrow_function = method.clone();
-            let mut borrow_function = borrow_function.borrow_mut();
-            return Ok(borrow_function.bind(
-                EnvironmentValue::LoxInstance(Rc::new(RefCell::new(self.clone()))),
-                interpreter,
-            )?);
+        if let Some(method) = heap.get(this.belong_class).find_method(heap, name.symbol) {
+            let method = heap.get(method).clone();
+            return Ok(method.bind(EnvironmentValue::LoxInstance(instance), interpreter));
         }
 
-        Err(Error {
-            line: name.line,
-            column: name.column,
-            message: format!("Undefined property {}", &name.lexeme),
-        })
+        Err(Error::at(
+            name,
+            "E0306",
+            format!("Undefined property {}", &name.lexeme),
+        ))
     }
 
     pub fn set(&mut self, name: &Token, value: EnvironmentValue) {
-        self.fields.insert(name.lexeme.as_ptr(), value);
+        if self.fields.insert(name.symbol, value).is_none() {
+            self.charge
+                .grow(mem::size_of::<(Symbol, EnvironmentValue)>());
+        }
+    }
+}
+
+impl Trace for LoxInstance {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.mark(self.belong_class);
+        for value in self.fields.values() {
+            tracer.mark_value(value);
+        }
     }
 }
@@ -1 +1,71 @@
+use crate::environment::{environment_value::EnvironmentValue, heap::Heap};
 
+use std::{fmt, rc::Rc};
+
+/// A host function, `heap` is the tree-walker's and `None` when the vm calls it.
+pub type NativeFunction =
+    dyn Fn(Option<&mut Heap>, &[EnvironmentValue]) -> Result<EnvironmentValue, String>;
+
+#[derive(Clone)]
+pub struct LoxNativeFunction {
+    name: String,
+    arity: usize,
+    function: Rc<NativeFunction>,
+    receiver: Option<EnvironmentValue>,
+}
+
+impl fmt::Debug for LoxNativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LoxNativeFunction")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish()
+    }
+}
+
+impl LoxNativeFunction {
+    pub fn new(name: &str, arity: usize, function: Rc<NativeFunction>) -> Self {
+        LoxNativeFunction {
+            name: name.to_string(),
+            arity,
+            function,
+            receiver: None,
+        }
+    }
+
+    /// A copy that gets `receiver` as its first argument, e.g. the list a method is called on.
+    pub fn bind(self, receiver: EnvironmentValue) -> Self {
+        LoxNativeFunction {
+            receiver: Some(receiver),
+            ..self
+        }
+    }
+
+    pub fn receiver(&self) -> Option<&EnvironmentValue> {
+        self.receiver.as_ref()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn arity(&self) -> usize {
+        self.arity
+    }
+
+    pub fn call(
+        &self,
+        heap: Option<&mut Heap>,
+        args: &[EnvironmentValue],
+    ) -> Result<EnvironmentValue, String> {
+        match &self.receiver {
+            Some(receiver) => {
+                let mut bound_args = Vec::with_capacity(args.len() + 1);
+                bound_args.push(receiver.clone());
+                bound_args.extend_from_slice(args);
+                (self.function)(heap, &bound_args)
+            }
+            None => (self.function)(heap, args),
+        }
+    }
+}
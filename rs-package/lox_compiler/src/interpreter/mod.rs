@@ -1,4 +1,6 @@
+pub mod define_native;
 pub mod interpreter;
 pub mod lox_class;
 pub mod lox_function;
 pub mod lox_instance;
+pub mod lox_native;
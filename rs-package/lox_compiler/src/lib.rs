@@ -1,36 +1,144 @@
-mod environment;
-mod interpreter;
+pub mod diagnostics;
+pub mod environment;
+mod error;
+pub mod interpreter;
+mod limits;
+mod memory;
 pub mod parser;
 pub mod scanner;
 mod semantic;
-mod utils;
+pub mod vm;
 
+pub use environment::environment_value::EnvironmentValue;
+pub use error::LoxError;
+pub use interpreter::interpreter::{Backend, Interpreter};
+pub use limits::ExecutionLimits;
+pub use scanner::scanner::CommentSyntax;
+
+use parser::{expression::NodeId, statement::Stmt};
+use scanner::interner::Interner;
 use std::{collections::VecDeque, rc::Rc};
 
-pub fn parse_token(code: &String) -> VecDeque<Rc<scanner::tokens::Token>> {
-    let mut s = scanner::scanner::Scanner::new(code);
-    s.scan();
-    s.tokens
+pub fn parse_token(code: &str) -> Result<VecDeque<Rc<scanner::tokens::Token>>, LoxError> {
+    parse_token_with_comments(code, CommentSyntax::default())
+}
+
+/// Like `parse_token`, with `comments` deciding what starts a line comment.
+pub fn parse_token_with_comments(
+    code: &str,
+    comments: CommentSyntax,
+) -> Result<VecDeque<Rc<scanner::tokens::Token>>, LoxError> {
+    scan_with_interner(code, &mut Interner::new(), comments)
+}
+
+pub fn parse(code: &str) -> Result<Rc<Vec<Stmt>>, LoxError> {
+    parse_with_comments(code, CommentSyntax::default())
 }
 
-pub fn parse(code: &String) -> Rc<Vec<parser::statement::Stmt>> {
-    let tokens = parse_token(code);
+/// Like `parse`, with `comments` deciding what starts a line comment.
+pub fn parse_with_comments(code: &str, comments: CommentSyntax) -> Result<Rc<Vec<Stmt>>, LoxError> {
+    parse_with_interner(code, &mut Interner::new(), &mut 0, comments)
+}
+
+/// Parses as much of `code` as it can, returning the partial AST with every syntax error.
+/// Only scan errors fail the whole call.
+pub fn parse_all(code: &str) -> Result<(Rc<Vec<Stmt>>, Vec<scanner::scanner::Error>), LoxError> {
+    let tokens = scan_with_interner(code, &mut Interner::new(), CommentSyntax::default())?;
     let mut p = parser::parser::Parser::new(tokens);
-    p.parse();
-    Rc::new(p.statements)
+    let errors = p.parse().err().unwrap_or_default();
+    Ok((Rc::new(p.statements), errors))
 }
 
-pub fn interpret(code: &String, log_fn: Option<fn(String) -> ()>) {
-    let statements = parse(code);
+fn scan_with_interner(
+    code: &str,
+    interner: &mut Interner,
+    comments: CommentSyntax,
+) -> Result<VecDeque<Rc<scanner::tokens::Token>>, LoxError> {
+    let mut s = scanner::scanner::Scanner::with_interner(code, std::mem::take(interner));
+    s.comments = comments;
+    let result = s.scan();
+    *interner = std::mem::take(&mut s.interner);
+    result.map_err(LoxError::ScanError)?;
+    Ok(s.tokens)
+}
+
+fn parse_with_interner(
+    code: &str,
+    interner: &mut Interner,
+    node_count: &mut NodeId,
+    comments: CommentSyntax,
+) -> Result<Rc<Vec<Stmt>>, LoxError> {
+    let tokens = scan_with_interner(code, interner, comments)?;
+    let mut p = parser::parser::Parser::with_node_count(tokens, *node_count);
+    let result = p.parse();
+    *node_count = p.node_count;
+    result.map_err(LoxError::ParseError)?;
+    Ok(Rc::new(p.statements))
+}
+
+pub fn interpret(code: &str, log_fn: Option<fn(String) -> ()>) -> Result<(), LoxError> {
+    interpret_with_backend(code, log_fn, Backend::TreeWalker)
+}
+
+pub fn interpret_with_backend(
+    code: &str,
+    log_fn: Option<fn(String) -> ()>,
+    backend: Backend,
+) -> Result<(), LoxError> {
+    let mut inter = Interpreter::with_backend(log_fn, backend);
+    interpret_with(code, &mut inter)
+}
+
+/// Runs `code` on an existing interpreter, keeping its globals and registered natives.
+pub fn interpret_with(code: &str, inter: &mut Interpreter) -> Result<(), LoxError> {
+    interpret_repl(code, inter, false)?;
+    Ok(())
+}
+
+/// Like `interpret_with`, but when `echo` is set a trailing expression statement
+/// is returned instead of discarded, so a REPL can print it.
+pub fn interpret_repl(
+    code: &str,
+    inter: &mut Interpreter,
+    echo: bool,
+) -> Result<Option<EnvironmentValue>, LoxError> {
+    let statements = parse_with_interner(
+        code,
+        &mut inter.interner,
+        &mut inter.node_count,
+        inter.comments,
+    )?;
 
     let mut s_a = semantic::scope_analyst::ScopeAnalyst::new(statements.clone());
-    s_a.analysis();
+    s_a.analysis().map_err(LoxError::ResolveError)?;
+
+    let to_runtime_error = |err: scanner::scanner::Error| {
+        if err.code == limits::LIMIT_EXCEEDED {
+            LoxError::LimitExceeded(vec![err])
+        } else {
+            LoxError::RuntimeError(vec![err])
+        }
+    };
+
+    if inter.backend == Backend::Vm {
+        return inter
+            .vm
+            .interpret(&statements, &s_a.scope_record, echo)
+            .map_err(to_runtime_error);
+    }
 
-    let mut inter = interpreter::interpreter::Interpreter::new(
-        statements.clone(),
-        s_a.scope_record.clone(),
-        log_fn,
-    );
+    if echo {
+        if let Some((Stmt::Expression(last), rest)) = statements.split_last() {
+            inter
+                .interpret(rest, &s_a.scope_record)
+                .map_err(to_runtime_error)?;
+            let value = inter.evaluate(&last.expression).map_err(to_runtime_error)?;
+            return Ok(Some(value));
+        }
+    }
 
-    inter.interpret();
+    inter
+        .interpret(&statements, &s_a.scope_record)
+        .map_err(to_runtime_error)?;
+    Ok(None)
 }
@@ -0,0 +1,152 @@
+use crate::memory;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Error code of every error raised by `ExecutionLimits`.
+pub const LIMIT_EXCEEDED: &str = "E0311";
+/// Error code of a script holding more memory than `ExecutionLimits::max_memory`.
+pub const MEMORY_EXCEEDED: &str = "E0312";
+
+/// Steps between two reads of the clock.
+const CLOCK_INTERVAL: u64 = 1024;
+
+/// Bounds on a script run by untrusted users, checked on every loop iteration and call.
+/// Each `interpret` call starts counting again.
+#[derive(Debug, Clone)]
+pub struct ExecutionLimits {
+    /// Loop iterations and calls the script may take.
+    pub max_steps: Option<u64>,
+    /// Wall-clock time the script may run for.
+    pub timeout: Option<Duration>,
+    /// Stops the script once set, e.g. from another thread.
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Bytes of live strings, lists, instances and environments the script may hold on top
+    /// of those already live when it started.
+    pub max_memory: Option<usize>,
+    /// Monotonic time measuring `timeout`, `std::time::Instant` is unavailable in the browser.
+    pub clock: fn() -> Duration,
+}
+
+impl Default for ExecutionLimits {
+    fn default() -> Self {
+        ExecutionLimits {
+            max_steps: None,
+            timeout: None,
+            cancel: None,
+            max_memory: None,
+            clock: instant_clock,
+        }
+    }
+}
+
+fn instant_clock() -> Duration {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed()
+}
+
+/// Steps taken and time left under `ExecutionLimits`.
+#[derive(Debug)]
+pub struct Budget {
+    limits: ExecutionLimits,
+    /// No limit is set, so steps don't need to be checked at all.
+    unlimited: bool,
+    steps: u64,
+    deadline: Option<Duration>,
+    /// Bytes live on this thread when the run started, held by earlier runs or other
+    /// interpreters rather than by this script.
+    baseline: usize,
+}
+
+impl Default for Budget {
+    fn default() -> Self {
+        Budget::new(ExecutionLimits::default())
+    }
+}
+
+impl Budget {
+    pub fn new(limits: ExecutionLimits) -> Self {
+        let unlimited = limits.max_steps.is_none()
+            && limits.timeout.is_none()
+            && limits.cancel.is_none()
+            && limits.max_memory.is_none();
+        Budget {
+            limits,
+            unlimited,
+            steps: 0,
+            deadline: None,
+            baseline: 0,
+        }
+    }
+
+    pub fn start(&mut self) {
+        self.steps = 0;
+        self.baseline = memory::live_bytes();
+        self.deadline = self
+            .limits
+            .timeout
+            .map(|timeout| (self.limits.clock)() + timeout);
+    }
+
+    /// Counts one step, failing with the code and message of the limit that was exceeded.
+    #[inline(always)]
+    pub fn step(&mut self) -> Result<(), (&'static str, String)> {
+        if self.unlimited {
+            return Ok(());
+        }
+        self.count_step()
+    }
+
+    #[inline(never)]
+    fn count_step(&mut self) -> Result<(), (&'static str, String)> {
+        self.steps += 1;
+        self.check_memory()?;
+
+        if let Some(max_steps) = self.limits.max_steps {
+            if self.steps > max_steps {
+                return Err(limit_exceeded(format!(
+                    "Execution limit exceeded, the script took more than {} steps",
+                    max_steps
+                )));
+            }
+        }
+        if let Some(cancel) = &self.limits.cancel {
+            if cancel.load(Ordering::Relaxed) {
+                return Err(limit_exceeded(String::from(
+                    "Execution limit exceeded, the script was cancelled",
+                )));
+            }
+        }
+        if let Some(deadline) = self.deadline {
+            if self.steps.is_multiple_of(CLOCK_INTERVAL) && (self.limits.clock)() > deadline {
+                return Err(limit_exceeded(format!(
+                    "Execution limit exceeded, the script ran longer than {:?}",
+                    self.limits.timeout.unwrap_or_default()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails once live values hold more memory than allowed, called after allocating.
+    #[inline]
+    pub fn check_memory(&self) -> Result<(), (&'static str, String)> {
+        if let Some(max_memory) = self.limits.max_memory {
+            if memory::live_bytes().saturating_sub(self.baseline) > max_memory {
+                return Err((
+                    MEMORY_EXCEEDED,
+                    format!(
+                        "Memory limit exceeded, the script holds more than {} bytes",
+                        max_memory
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn limit_exceeded(message: String) -> (&'static str, String) {
+    (LIMIT_EXCEEDED, message)
+}
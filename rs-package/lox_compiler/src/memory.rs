@@ -0,0 +1,45 @@
+use std::cell::Cell;
+
+thread_local! {
+    static LIVE_BYTES: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Bytes held by live strings, lists, instances and environments on this thread, interpreters
+/// running on the same thread share the count so a run is measured from where it started.
+pub fn live_bytes() -> usize {
+    LIVE_BYTES.with(|live| live.get())
+}
+
+/// Bytes counted as live for as long as the charge exists, cloning it charges again.
+#[derive(Debug, Default)]
+pub struct Charge(usize);
+
+impl Charge {
+    pub fn new(bytes: usize) -> Self {
+        LIVE_BYTES.with(|live| live.set(live.get() + bytes));
+        Charge(bytes)
+    }
+
+    pub fn grow(&mut self, bytes: usize) {
+        LIVE_BYTES.with(|live| live.set(live.get() + bytes));
+        self.0 += bytes;
+    }
+
+    pub fn shrink(&mut self, bytes: usize) {
+        let bytes = bytes.min(self.0);
+        LIVE_BYTES.with(|live| live.set(live.get() - bytes));
+        self.0 -= bytes;
+    }
+}
+
+impl Clone for Charge {
+    fn clone(&self) -> Self {
+        Charge::new(self.0)
+    }
+}
+
+impl Drop for Charge {
+    fn drop(&mut self) {
+        LIVE_BYTES.with(|live| live.set(live.get() - self.0));
+    }
+}
@@ -1,6 +1,9 @@
 use super::super::scanner::tokens::*;
 use std::rc::Rc;
 
+/// Numbered by the parser, resolver results are stored in tables indexed by it.
+pub type NodeId = usize;
+
 #[derive(Debug, Clone)]
 pub enum Expr {
     Binary(Rc<BinaryExpression>),
@@ -15,10 +18,60 @@ pub enum Expr {
     Set(Rc<SetExpression>),
     This(Rc<ThisExpression>),
     Super(Rc<SuperExpression>),
+    List(Rc<ListExpression>),
+    Map(Rc<MapExpression>),
+    Index(Rc<IndexExpression>),
+    IndexSet(Rc<IndexSetExpression>),
+}
+
+impl Expr {
+    pub fn id(&self) -> NodeId {
+        match self {
+            Expr::Binary(expr) => expr.id,
+            Expr::Logical(expr) => expr.id,
+            Expr::Grouping(expr) => expr.id,
+            Expr::Literal(expr) => expr.id,
+            Expr::Unary(expr) => expr.id,
+            Expr::Variable(expr) => expr.id,
+            Expr::Assignment(expr) => expr.id,
+            Expr::Call(expr) => expr.id,
+            Expr::Get(expr) => expr.id,
+            Expr::Set(expr) => expr.id,
+            Expr::This(expr) => expr.id,
+            Expr::Super(expr) => expr.id,
+            Expr::List(expr) => expr.id,
+            Expr::Map(expr) => expr.id,
+            Expr::Index(expr) => expr.id,
+            Expr::IndexSet(expr) => expr.id,
+        }
+    }
+
+    /// The token runtime errors about the whole expression point at, literals have none.
+    pub fn token(&self) -> Option<&Token> {
+        match self {
+            Expr::Binary(expr) => Some(&expr.operator),
+            Expr::Logical(expr) => Some(&expr.operator),
+            Expr::Grouping(expr) => Some(&expr.start_parenthese),
+            Expr::Literal(_) => None,
+            Expr::Unary(expr) => Some(&expr.operator),
+            Expr::Variable(expr) => Some(&expr.name),
+            Expr::Assignment(expr) => Some(&expr.name),
+            Expr::Call(expr) => Some(&expr.end_parenthese),
+            Expr::Get(expr) => Some(&expr.name),
+            Expr::Set(expr) => Some(&expr.name),
+            Expr::This(expr) => Some(&expr.keyword),
+            Expr::Super(expr) => Some(&expr.keyword),
+            Expr::List(expr) => Some(&expr.end_bracket),
+            Expr::Map(expr) => Some(&expr.end_brace),
+            Expr::Index(expr) => Some(&expr.end_bracket),
+            Expr::IndexSet(expr) => Some(&expr.end_bracket),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct BinaryExpression {
+    pub id: NodeId,
     pub left: Expr,
     pub operator: Token,
     pub right: Expr,
@@ -26,6 +79,7 @@ pub struct BinaryExpression {
 
 #[derive(Debug)]
 pub struct LogicalExpression {
+    pub id: NodeId,
     pub left: Expr,
     pub operator: Token,
     pub right: Expr,
@@ -33,33 +87,40 @@ pub struct LogicalExpression {
 
 #[derive(Debug)]
 pub struct GroupingExpression {
+    pub id: NodeId,
+    pub start_parenthese: Token,
     pub expression: Expr,
 }
 
 #[derive(Debug, Clone)]
 pub struct LiteralExpression {
+    pub id: NodeId,
     pub value: Option<ValueType>,
 }
 
 #[derive(Debug)]
 pub struct UnaryExpression {
+    pub id: NodeId,
     pub operator: Token,
     pub expression: Expr,
 }
 
 #[derive(Debug, Clone)]
 pub struct VariableExpression {
+    pub id: NodeId,
     pub name: Token,
 }
 
 #[derive(Debug)]
 pub struct AssignmentExpression {
+    pub id: NodeId,
     pub name: Token,
     pub value: Expr,
 }
 
 #[derive(Debug)]
 pub struct CallExpression {
+    pub id: NodeId,
     pub callee: Expr,
     pub args: Vec<Expr>,
     pub end_parenthese: Token,
@@ -67,12 +128,14 @@ pub struct CallExpression {
 
 #[derive(Debug)]
 pub struct GetExpression {
+    pub id: NodeId,
     pub object: Expr,
     pub name: Token,
 }
 
 #[derive(Debug)]
 pub struct SetExpression {
+    pub id: NodeId,
     pub object: Expr,
     pub name: Token,
     pub value: Expr,
@@ -80,11 +143,44 @@ pub struct SetExpression {
 
 #[derive(Debug)]
 pub struct ThisExpression {
+    pub id: NodeId,
     pub keyword: Token,
 }
 
 #[derive(Debug, Clone)]
 pub struct SuperExpression {
+    pub id: NodeId,
     pub keyword: Token,
     pub method: Token,
 }
+
+#[derive(Debug)]
+pub struct ListExpression {
+    pub id: NodeId,
+    pub elements: Vec<Expr>,
+    pub end_bracket: Token,
+}
+
+#[derive(Debug)]
+pub struct MapExpression {
+    pub id: NodeId,
+    pub entries: Vec<(Expr, Expr)>,
+    pub end_brace: Token,
+}
+
+#[derive(Debug)]
+pub struct IndexExpression {
+    pub id: NodeId,
+    pub object: Expr,
+    pub index: Expr,
+    pub end_bracket: Token,
+}
+
+#[derive(Debug)]
+pub struct IndexSetExpression {
+    pub id: NodeId,
+    pub object: Expr,
+    pub index: Expr,
+    pub value: Expr,
+    pub end_bracket: Token,
+}
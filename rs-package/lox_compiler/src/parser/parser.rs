@@ -11,29 +11,34 @@ use std::rc::Rc;
  * function       → IDENTIFIER "(" parameters? ")" block
  * parameters     → IDENTIFIER ( "," IDENTIFIER )*
  * varDecl        → "var" IDENTIFIER ( "=" expression )? ";"
- * statement      → exprStmt | forStmt | ifStmt | printStmt | returnStmt | whileStmt | block
+ * statement      → exprStmt | forStmt | ifStmt | printStmt | returnStmt | whileStmt | breakStmt
+ *                | continueStmt | block
  * exprStmt       → expression ";"
  * ifStmt         → "if" "(" expression ")" statement ( "else" statement )?
  * forStmt        → "for" "(" ( varDecl | exprStmt | ";" ) expression? ";" expression? ")" statement
  * printStmt      → "print" expression ";"
  * returnStmt     → "return" expression? ";"
  * whileStmt      → "while" "(" expression ")" statement
+ * breakStmt      → "break" ";"
+ * continueStmt   → "continue" ";"
  * block          → "{" declaration* "}" ;
  *
  *
  *
  * expression     → assignment
- * assignment     → ( call "." )? IDENTIFIER "=" assignment | logicOr
+ * assignment     → ( call "." )? IDENTIFIER "=" assignment | call "[" expression "]" "=" assignment | logicOr
  * logicOr        → logicAnd ("or" logicAnd)*
  * logicAnd       → equality ("and" equality)*
  * equality       → comparison ( ( "!=" | "==" ) comparison )*
  * comparison     → term ( ( ">" | ">=" | "<" | "<=" ) term )*
  * term           → factor ( ( "-" | "+" ) factor )*
- * factor         → unary ( ( "/" | "*" ) unary )*
- * unary          → ( "!" | "-" ) unary | call
- * call           → primary ( "(" arguments? ")" | "." IDENTIFIER )*
+ * factor         → unary ( ( "/" | "*" | "%" | "//" ) unary )*
+ * unary          → ( "!" | "-" ) unary | power
+ * power          → call ( "**" unary )?
+ * call           → primary ( "(" arguments? ")" | "." IDENTIFIER | "[" expression "]" )*
  * arguments      → expression ( "," expression )*
- * primary        → NUMBER | STRING | "true" | "false" | "nil" | "this" | IDENTIFIER | "(" expression ")" | "super" "." IDENTIFIER
+ * primary        → NUMBER | STRING | "true" | "false" | "nil" | "this" | IDENTIFIER | "(" expression ")" | "super" "." IDENTIFIER | "[" arguments? "]"
+ *                | "{" ( expression ":" expression ( "," expression ":" expression )* )? "}"
  */
 
 /*
@@ -66,45 +71,47 @@ enum FunType {
 #[derive(Debug)]
 pub struct Parser {
     pub tokens: VecDeque<Rc<Token>>,
-    pub current: u8,
+    pub current: usize,
     pub statements: Vec<Stmt>,
     pub errors: Vec<Error>,
-    pub expr_count: usize,
+    /// Id of the next node, it continues from earlier inputs so ids stay unique in a REPL.
+    pub node_count: NodeId,
+    /// Loops around the statement being parsed, within the innermost function.
+    loop_depth: usize,
 }
 
 impl Parser {
     pub fn new(tokens: VecDeque<Rc<Token>>) -> Self {
+        Parser::with_node_count(tokens, 0)
+    }
+
+    pub fn with_node_count(tokens: VecDeque<Rc<Token>>, node_count: NodeId) -> Self {
         Parser {
             tokens,
             current: 0,
             statements: Vec::new(),
             errors: Vec::new(),
-            expr_count: 0,
+            node_count,
+            loop_depth: 0,
         }
     }
 
-    pub fn parse(&mut self) {
+    /// Parses the whole input, recovering at statement boundaries so every syntax error is
+    /// collected. `statements` keeps the declarations that parsed even when errors are returned.
+    pub fn parse(&mut self) -> Result<(), Vec<Error>> {
         while !self.is_end() {
             match self.declaration() {
                 Ok(stmt) => {
                     self.statements.push(stmt);
                 }
-                Err(_) => {
-                    self.synchronize();
-                    let errors = self
-                        .errors
-                        .iter()
-                        .map(|err| {
-                            format!(
-                                "{} in line {} column {} \n",
-                                err.message, err.line, err.column
-                            )
-                        })
-                        .collect::<String>();
-                    panic!("\n\n******\nOops! parse errors:\n{}******\n\n", errors);
-                }
+                Err(_) => self.synchronize(),
             }
         }
+
+        if !self.errors.is_empty() {
+            return Err(self.errors.clone());
+        }
+        Ok(())
     }
 
     fn declaration(&mut self) -> Result<Stmt, ()> {
@@ -154,9 +161,22 @@ impl Parser {
             format!(r#"Expect "{{" before {:?} body"#, fun_type),
         )?;
 
-        let body = self.block()?;
-        let body = BlockStatement { statements: body };
-        Ok(FunctionStatement { name, params, body })
+        // A function body starts outside of any loop, even when declared inside one.
+        let start_brace = clone_previous_token!(self);
+        let loop_depth = std::mem::replace(&mut self.loop_depth, 0);
+        let body = self.block();
+        self.loop_depth = loop_depth;
+        let body = BlockStatement {
+            id: self.next_id(),
+            start_brace,
+            statements: body?,
+        };
+        Ok(FunctionStatement {
+            id: self.next_id(),
+            name,
+            params,
+            body,
+        })
     }
 
     fn class_decl(&mut self) -> Result<ClassStatement, ()> {
@@ -165,13 +185,13 @@ impl Parser {
 
         let mut superclass = None;
         if self.match_token(TokensType::Less) {
-            let superclass_name = self.previous();
-            let superclass_name = superclass_name.clone();
-            self.consume(
+            let superclass_name = self.consume(
                 TokensType::Identifier,
-                String::from("Expect superclass name'"),
+                String::from("Expect superclass name"),
             )?;
+            let superclass_name = superclass_name.clone();
             superclass = Some(Expr::Variable(Rc::new(VariableExpression {
+                id: self.next_id(),
                 name: superclass_name,
             })));
         }
@@ -191,6 +211,7 @@ impl Parser {
         )?;
 
         Ok(ClassStatement {
+            id: self.next_id(),
             name,
             superclass,
             methods,
@@ -209,15 +230,21 @@ impl Parser {
             TokensType::Semicolon,
             String::from(r#"Expect ";" after variable declaration"#),
         )?;
-        Ok(VarStatement { name, initializer })
+        Ok(VarStatement {
+            id: self.next_id(),
+            name,
+            initializer,
+        })
     }
 
     fn block(&mut self) -> Result<Vec<Stmt>, ()> {
         let mut statements = Vec::new();
 
         while !self.check(TokensType::RightBrace) && !self.is_end() {
-            let stmt = self.declaration()?;
-            statements.push(stmt);
+            match self.declaration() {
+                Ok(stmt) => statements.push(stmt),
+                Err(_) => self.synchronize(),
+            }
         }
 
         self.consume(
@@ -248,9 +275,17 @@ impl Parser {
             return self.for_stmt();
         }
 
+        if self.match_token(TokensType::Break) || self.match_token(TokensType::Continue) {
+            return self.loop_jump_stmt();
+        }
+
         if self.match_token(TokensType::LeftBrace) {
+            let start_brace = clone_previous_token!(self);
+            let statements = self.block()?;
             return Ok(Stmt::Block(Rc::new(BlockStatement {
-                statements: self.block()?,
+                id: self.next_id(),
+                start_brace,
+                statements,
             })));
         }
 
@@ -258,6 +293,7 @@ impl Parser {
     }
 
     fn if_stmt(&mut self) -> Result<Stmt, ()> {
+        let keyword = clone_previous_token!(self);
         self.consume(
             TokensType::LeftParen,
             String::from(r#"Expect "(" after "if""#),
@@ -275,6 +311,8 @@ impl Parser {
         }
 
         Ok(Stmt::If(Rc::new(IfStatement {
+            id: self.next_id(),
+            keyword,
             condition,
             then_branch,
             else_branch,
@@ -289,6 +327,7 @@ impl Parser {
             String::from(r#"Expect ";" after value"#),
         )?;
         Ok(Stmt::Print(PrintStatement {
+            id: self.next_id(),
             keyword,
             expression,
         }))
@@ -296,11 +335,9 @@ impl Parser {
 
     fn return_stmt(&mut self) -> Result<Stmt, ()> {
         let keyword = clone_previous_token!(self);
-        let value;
-        if self.check(TokensType::Semicolon) {
-            value = Expr::Literal(LiteralExpression { value: None });
-        } else {
-            value = self.expression()?;
+        let mut value = None;
+        if !self.check(TokensType::Semicolon) {
+            value = Some(self.expression()?);
         }
 
         self.consume(
@@ -308,10 +345,15 @@ impl Parser {
             String::from(r#"Expect ";" after return value"#),
         )?;
 
-        Ok(Stmt::Return(ReturnStatement { keyword, value }))
+        Ok(Stmt::Return(ReturnStatement {
+            id: self.next_id(),
+            keyword,
+            value,
+        }))
     }
 
     fn while_stmt(&mut self) -> Result<Stmt, ()> {
+        let keyword = clone_previous_token!(self);
         self.consume(
             TokensType::LeftParen,
             String::from(r#"Expect "(" after "while""#),
@@ -321,22 +363,28 @@ impl Parser {
             TokensType::RightParen,
             String::from(r#"Expect ")" after condition"#),
         )?;
-        let body = self.statement()?;
-        Ok(Stmt::While(Rc::new(WhileStatement { condition, body })))
+        let body = self.loop_body()?;
+        Ok(Stmt::While(Rc::new(WhileStatement {
+            id: self.next_id(),
+            keyword,
+            condition,
+            body,
+        })))
     }
 
     fn for_stmt(&mut self) -> Result<Stmt, ()> {
+        let keyword = clone_previous_token!(self);
         self.consume(
             TokensType::LeftParen,
             String::from(r#"Expect "(" after "for""#),
         )?;
 
         if self.check(TokensType::RightParen) {
-            self.errors.push(Error {
-                line: self.peek().line,
-                column: self.peek().column,
-                message: String::from(r#"There is nothing exist in the parenthese of "for""#),
-            });
+            self.errors.push(Error::at(
+                self.peek(),
+                "E0103",
+                String::from(r#"There is nothing exist in the parenthese of "for""#),
+            ));
             return Err(());
         }
 
@@ -365,9 +413,11 @@ impl Parser {
                 String::from(r#"Expect ")" after the parenthese of "for""#),
             )?;
         }
-        let body = self.statement()?;
+        let body = self.loop_body()?;
 
         Ok(Stmt::For(Rc::new(ForStatement {
+            id: self.next_id(),
+            keyword,
             initializer,
             condition,
             updator,
@@ -375,13 +425,48 @@ impl Parser {
         })))
     }
 
+    fn loop_body(&mut self) -> Result<Stmt, ()> {
+        self.loop_depth += 1;
+        let body = self.statement();
+        self.loop_depth -= 1;
+        body
+    }
+
+    fn loop_jump_stmt(&mut self) -> Result<Stmt, ()> {
+        let keyword = clone_previous_token!(self);
+        if self.loop_depth == 0 {
+            self.errors.push(
+                Error::at(
+                    &keyword,
+                    "E0104",
+                    format!(r#"Can't use "{}" outside of a loop"#, keyword.lexeme),
+                )
+                .with_note(r#"note: it is only allowed inside "while" and "for" loops"#),
+            );
+        }
+        self.consume(
+            TokensType::Semicolon,
+            format!(r#"Expect ";" after "{}""#, keyword.lexeme),
+        )?;
+
+        let id = self.next_id();
+        if keyword.token_type == TokensType::Break {
+            Ok(Stmt::Break(BreakStatement { id, keyword }))
+        } else {
+            Ok(Stmt::Continue(ContinueStatement { id, keyword }))
+        }
+    }
+
     fn expr_stmt(&mut self) -> Result<Stmt, ()> {
         let expression = self.expression()?;
         self.consume(
             TokensType::Semicolon,
             String::from(r#"Expect ";" after expression"#),
         )?;
-        Ok(Stmt::Expression(ExpressionStatement { expression }))
+        Ok(Stmt::Expression(ExpressionStatement {
+            id: self.next_id(),
+            expression,
+        }))
     }
 
     fn expression(&mut self) -> Result<Expr, ()> {
@@ -399,25 +484,36 @@ impl Parser {
                 Expr::Variable(variable) => {
                     let name = variable.name.clone();
                     return Ok(Expr::Assignment(Rc::new(AssignmentExpression {
+                        id: self.next_id(),
                         name,
                         value,
                     })));
                 }
                 Expr::Get(get_expression) => {
                     return Ok(Expr::Set(Rc::new(SetExpression {
+                        id: self.next_id(),
                         object: get_expression.object.clone(),
                         name: get_expression.name.clone(),
                         value,
                     })));
                 }
+                Expr::Index(index_expression) => {
+                    return Ok(Expr::IndexSet(Rc::new(IndexSetExpression {
+                        id: self.next_id(),
+                        object: index_expression.object.clone(),
+                        index: index_expression.index.clone(),
+                        value,
+                        end_bracket: index_expression.end_bracket.clone(),
+                    })));
+                }
                 _ => {}
             }
 
-            self.errors.push(Error {
-                line: equals.line,
-                column: equals.column,
-                message: String::from("Invalid assignment target"),
-            });
+            self.errors.push(
+                Error::at(&equals, "E0102", String::from("Invalid assignment target")).with_note(
+                    "note: only variables, properties, list elements and map entries can be assigned to",
+                ),
+            );
             return Err(());
         }
         Ok(expression)
@@ -426,34 +522,40 @@ impl Parser {
     fn primary(&mut self) -> Result<Expr, ()> {
         if self.match_token(TokensType::Number) || self.match_token(TokensType::String) {
             let literal = clone_previous_token!(self).literal;
-            self.increase_expr_count();
-            return Ok(Expr::Literal(LiteralExpression { value: literal }));
+            return Ok(Expr::Literal(LiteralExpression {
+                id: self.next_id(),
+                value: literal,
+            }));
         }
 
         if self.match_token(TokensType::True) {
-            self.increase_expr_count();
             return Ok(Expr::Literal(LiteralExpression {
+                id: self.next_id(),
                 value: Some(ValueType::Bool(true)),
             }));
         }
 
         if self.match_token(TokensType::False) {
-            self.increase_expr_count();
             return Ok(Expr::Literal(LiteralExpression {
+                id: self.next_id(),
                 value: Some(ValueType::Bool(false)),
             }));
         }
 
         if self.match_token(TokensType::Nil) {
-            self.increase_expr_count();
-            return Ok(Expr::Literal(LiteralExpression { value: None }));
+            return Ok(Expr::Literal(LiteralExpression {
+                id: self.next_id(),
+                value: None,
+            }));
         }
 
         if self.match_token(TokensType::This) {
             let keyword = self.previous();
             let keyword = keyword.clone();
-            self.increase_expr_count();
-            return Ok(Expr::This(Rc::new(ThisExpression { keyword })));
+            return Ok(Expr::This(Rc::new(ThisExpression {
+                id: self.next_id(),
+                keyword,
+            })));
         }
 
         if self.match_token(TokensType::Super) {
@@ -464,33 +566,90 @@ impl Parser {
                 String::from("Expect superclass method name"),
             )?;
             let method = clone_previous_token!(self);
-            self.increase_expr_count();
-            return Ok(Expr::Super(Rc::new(SuperExpression { keyword, method })));
+            return Ok(Expr::Super(Rc::new(SuperExpression {
+                id: self.next_id(),
+                keyword,
+                method,
+            })));
         }
 
         if self.match_token(TokensType::Identifier) {
             let name = clone_previous_token!(self);
-            self.increase_expr_count();
-            return Ok(Expr::Variable(Rc::new(VariableExpression { name })));
+            return Ok(Expr::Variable(Rc::new(VariableExpression {
+                id: self.next_id(),
+                name,
+            })));
+        }
+
+        if self.match_token(TokensType::LeftBracket) {
+            let mut elements = Vec::new();
+            if !self.check(TokensType::RightBracket) {
+                loop {
+                    elements.push(self.expression()?);
+                    if !self.match_token(TokensType::Comma) {
+                        break;
+                    }
+                }
+            }
+            let end_bracket = self.consume(
+                TokensType::RightBracket,
+                String::from(r#"Expect "]" after list elements"#),
+            )?;
+            let end_bracket = end_bracket.clone();
+            return Ok(Expr::List(Rc::new(ListExpression {
+                id: self.next_id(),
+                elements,
+                end_bracket,
+            })));
+        }
+
+        if self.match_token(TokensType::LeftBrace) {
+            let mut entries = Vec::new();
+            if !self.check(TokensType::RightBrace) {
+                loop {
+                    let key = self.expression()?;
+                    self.consume(
+                        TokensType::Colon,
+                        String::from(r#"Expect ":" after map key"#),
+                    )?;
+                    let value = self.expression()?;
+                    entries.push((key, value));
+                    if !self.match_token(TokensType::Comma) {
+                        break;
+                    }
+                }
+            }
+            let end_brace = self.consume(
+                TokensType::RightBrace,
+                String::from(r#"Expect "}" after map entries"#),
+            )?;
+            let end_brace = end_brace.clone();
+            return Ok(Expr::Map(Rc::new(MapExpression {
+                id: self.next_id(),
+                entries,
+                end_brace,
+            })));
         }
 
         if self.match_token(TokensType::LeftParen) {
+            let start_parenthese = clone_previous_token!(self);
             let expression = Ok(Expr::Grouping(Rc::new(GroupingExpression {
+                id: self.next_id(),
+                start_parenthese,
                 expression: self.expression()?,
             })));
             self.consume(
                 TokensType::RightParen,
                 String::from(r#"Expect ")" after expression"#),
             )?;
-            self.increase_expr_count();
             return expression;
         }
 
-        self.errors.push(Error {
-            line: self.peek().line,
-            column: self.peek().column,
-            message: format!(r#"Unexpected token "{:?}""#, self.peek().lexeme),
-        });
+        self.errors.push(Error::at(
+            self.peek(),
+            "E0101",
+            format!(r#"Unexpected token "{}""#, self.peek().lexeme),
+        ));
 
         Err(())
     }
@@ -501,10 +660,8 @@ impl Parser {
         while self.match_token(TokensType::Or) {
             let operator = clone_previous_token!(self);
             let right = self.logic_and()?;
-
-            self.increase_expr_count();
-
             expression = Expr::Logical(Rc::new(LogicalExpression {
+                id: self.next_id(),
                 left: expression,
                 operator,
                 right,
@@ -520,10 +677,8 @@ impl Parser {
         while self.match_token(TokensType::And) {
             let operator = clone_previous_token!(self);
             let right = self.equality()?;
-
-            self.increase_expr_count();
-
             expression = Expr::Logical(Rc::new(LogicalExpression {
+                id: self.next_id(),
                 left: expression,
                 operator,
                 right,
@@ -539,10 +694,8 @@ impl Parser {
         while self.match_token(TokensType::BangEqual) || self.match_token(TokensType::EqualEqual) {
             let operator = clone_previous_token!(self);
             let right = self.comparison()?;
-
-            self.increase_expr_count();
-
             expression = Expr::Binary(Rc::new(BinaryExpression {
+                id: self.next_id(),
                 left: expression,
                 operator,
                 right,
@@ -562,10 +715,8 @@ impl Parser {
         {
             let operator = clone_previous_token!(self);
             let right = self.term()?;
-
-            self.increase_expr_count();
-
             expression = Expr::Binary(Rc::new(BinaryExpression {
+                id: self.next_id(),
                 left: expression,
                 operator,
                 right,
@@ -581,10 +732,8 @@ impl Parser {
         while self.match_token(TokensType::Minus) || self.match_token(TokensType::Plus) {
             let operator = clone_previous_token!(self);
             let right = self.factor()?;
-
-            self.increase_expr_count();
-
             expression = Expr::Binary(Rc::new(BinaryExpression {
+                id: self.next_id(),
                 left: expression,
                 operator,
                 right,
@@ -597,13 +746,15 @@ impl Parser {
     fn factor(&mut self) -> Result<Expr, ()> {
         let mut expression = self.unary()?;
 
-        while self.match_token(TokensType::Slash) || self.match_token(TokensType::Star) {
+        while self.match_token(TokensType::Slash)
+            || self.match_token(TokensType::Star)
+            || self.match_token(TokensType::Percent)
+            || self.match_token(TokensType::SlashSlash)
+        {
             let operator = clone_previous_token!(self);
             let right = self.unary()?;
-
-            self.increase_expr_count();
-
             expression = Expr::Binary(Rc::new(BinaryExpression {
+                id: self.next_id(),
                 left: expression,
                 operator,
                 right,
@@ -617,35 +768,67 @@ impl Parser {
         if self.match_token(TokensType::Bang) || self.match_token(TokensType::Minus) {
             let operator = clone_previous_token!(self);
             let right = self.unary()?;
-
-            self.increase_expr_count();
-
             return Ok(Expr::Unary(Rc::new(UnaryExpression {
+                id: self.next_id(),
                 operator,
                 expression: right,
             })));
         }
 
-        Ok(self.call()?)
+        self.power()
+    }
+
+    /// `**` binds tighter than a unary operator on its left, `-2 ** 2` is `-(2 ** 2)`, and
+    /// is right-associative since its right operand is parsed as a unary.
+    fn power(&mut self) -> Result<Expr, ()> {
+        let expression = self.call()?;
+
+        if self.match_token(TokensType::StarStar) {
+            let operator = clone_previous_token!(self);
+            let right = self.unary()?;
+            return Ok(Expr::Binary(Rc::new(BinaryExpression {
+                id: self.next_id(),
+                left: expression,
+                operator,
+                right,
+            })));
+        }
+
+        Ok(expression)
     }
 
     fn call(&mut self) -> Result<Expr, ()> {
         let mut expression = self.primary()?;
 
-        while self.match_token(TokensType::LeftParen) || self.match_token(TokensType::Dot) {
+        while self.match_token(TokensType::LeftParen)
+            || self.match_token(TokensType::Dot)
+            || self.match_token(TokensType::LeftBracket)
+        {
             let previous_type = self.previous().token_type;
             match previous_type {
                 TokensType::LeftParen => expression = self.finish_call(expression)?,
+                TokensType::LeftBracket => {
+                    let index = self.expression()?;
+                    let end_bracket = self.consume(
+                        TokensType::RightBracket,
+                        String::from(r#"Expect "]" after index"#),
+                    )?;
+                    let end_bracket = end_bracket.clone();
+                    expression = Expr::Index(Rc::new(IndexExpression {
+                        id: self.next_id(),
+                        object: expression,
+                        index,
+                        end_bracket,
+                    }))
+                }
                 TokensType::Dot => {
                     let name = self.consume(
                         TokensType::Identifier,
                         String::from(r#"Expect property name after ".""#),
                     )?;
                     let name = name.clone();
-
-                    self.increase_expr_count();
-
                     expression = Expr::Get(Rc::new(GetExpression {
+                        id: self.next_id(),
                         object: expression,
                         name,
                     }))
@@ -674,10 +857,8 @@ impl Parser {
             String::from(r#"Expect ")" after arguments"#),
         )?;
         let end_parenthese = end_parenthese.clone();
-
-        self.increase_expr_count();
-
         Ok(Expr::Call(Rc::new(CallExpression {
+            id: self.next_id(),
             callee,
             args,
             end_parenthese,
@@ -714,11 +895,11 @@ impl Parser {
         // if self.current as usize >= self.tokens.len() {
         //     return None;
         // }
-        &self.tokens[self.current as usize]
+        &self.tokens[self.current]
     }
 
     fn previous(&self) -> &Token {
-        let index = (self.current - 1) as usize;
+        let index = self.current - 1;
         // if index >= self.tokens.len() {
         //     return None;
         // }
@@ -729,16 +910,14 @@ impl Parser {
         if self.check(token_type) {
             return Ok(self.advance());
         }
-        self.errors.push(Error {
-            line: self.previous().line,
-            column: self.previous().column,
-            message,
-        });
+        self.errors
+            .push(Error::at(self.previous(), "E0100", message));
         Err(())
     }
 
-    fn increase_expr_count(&mut self) {
-        self.expr_count = self.expr_count + 1;
+    fn next_id(&mut self) -> NodeId {
+        self.node_count += 1;
+        self.node_count - 1
     }
 
     fn synchronize(&mut self) {
@@ -759,7 +938,9 @@ impl Parser {
                 | TokensType::If
                 | TokensType::While
                 | TokensType::Print
-                | TokensType::Return => return (),
+                | TokensType::Return
+                | TokensType::Break
+                | TokensType::Continue => return (),
                 _ => {}
             }
 
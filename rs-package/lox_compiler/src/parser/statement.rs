@@ -13,16 +13,59 @@ pub enum Stmt {
     Block(Rc<BlockStatement>),
     Function(Rc<FunctionStatement>),
     Return(ReturnStatement),
+    Break(BreakStatement),
+    Continue(ContinueStatement),
     Class(Rc<ClassStatement>),
 }
 
+impl Stmt {
+    pub fn id(&self) -> NodeId {
+        match self {
+            Stmt::Expression(stmt) => stmt.id,
+            Stmt::If(stmt) => stmt.id,
+            Stmt::Print(stmt) => stmt.id,
+            Stmt::While(stmt) => stmt.id,
+            Stmt::For(stmt) => stmt.id,
+            Stmt::Var(stmt) => stmt.id,
+            Stmt::Block(stmt) => stmt.id,
+            Stmt::Function(stmt) => stmt.id,
+            Stmt::Return(stmt) => stmt.id,
+            Stmt::Break(stmt) => stmt.id,
+            Stmt::Continue(stmt) => stmt.id,
+            Stmt::Class(stmt) => stmt.id,
+        }
+    }
+
+    /// The token runtime errors about the whole statement point at, an expression statement
+    /// has none of its own.
+    pub fn token(&self) -> Option<&Token> {
+        match self {
+            Stmt::Expression(_) => None,
+            Stmt::If(stmt) => Some(&stmt.keyword),
+            Stmt::Print(stmt) => Some(&stmt.keyword),
+            Stmt::While(stmt) => Some(&stmt.keyword),
+            Stmt::For(stmt) => Some(&stmt.keyword),
+            Stmt::Var(stmt) => Some(&stmt.name),
+            Stmt::Block(stmt) => Some(&stmt.start_brace),
+            Stmt::Function(stmt) => Some(&stmt.name),
+            Stmt::Return(stmt) => Some(&stmt.keyword),
+            Stmt::Break(stmt) => Some(&stmt.keyword),
+            Stmt::Continue(stmt) => Some(&stmt.keyword),
+            Stmt::Class(stmt) => Some(&stmt.name),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ExpressionStatement {
+    pub id: NodeId,
     pub expression: Expr,
 }
 
 #[derive(Debug)]
 pub struct IfStatement {
+    pub id: NodeId,
+    pub keyword: Token,
     pub condition: Expr,
     pub then_branch: Stmt,
     pub else_branch: Option<Stmt>,
@@ -30,18 +73,23 @@ pub struct IfStatement {
 
 #[derive(Debug, Clone)]
 pub struct PrintStatement {
+    pub id: NodeId,
     pub keyword: Token,
     pub expression: Expr,
 }
 
 #[derive(Debug)]
 pub struct WhileStatement {
+    pub id: NodeId,
+    pub keyword: Token,
     pub condition: Expr,
     pub body: Stmt,
 }
 
 #[derive(Debug)]
 pub struct ForStatement {
+    pub id: NodeId,
+    pub keyword: Token,
     pub initializer: Option<Stmt>,
     pub condition: Option<Expr>,
     pub updator: Option<Expr>,
@@ -50,17 +98,21 @@ pub struct ForStatement {
 
 #[derive(Debug, Clone)]
 pub struct VarStatement {
+    pub id: NodeId,
     pub name: Token,
     pub initializer: Option<Expr>,
 }
 
 #[derive(Debug, Clone)]
 pub struct BlockStatement {
+    pub id: NodeId,
+    pub start_brace: Token,
     pub statements: Vec<Stmt>,
 }
 
 #[derive(Debug, Clone)]
 pub struct FunctionStatement {
+    pub id: NodeId,
     pub name: Token,
     pub params: Vec<Token>,
     pub body: BlockStatement,
@@ -68,12 +120,26 @@ pub struct FunctionStatement {
 
 #[derive(Debug, Clone)]
 pub struct ReturnStatement {
+    pub id: NodeId,
+    pub keyword: Token,
+    pub value: Option<Expr>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BreakStatement {
+    pub id: NodeId,
+    pub keyword: Token,
+}
+
+#[derive(Debug, Clone)]
+pub struct ContinueStatement {
+    pub id: NodeId,
     pub keyword: Token,
-    pub value: Expr,
 }
 
 #[derive(Debug, Clone)]
 pub struct ClassStatement {
+    pub id: NodeId,
     pub name: Token,
     pub superclass: Option<Expr>,
     pub methods: Vec<Rc<FunctionStatement>>,
@@ -0,0 +1,53 @@
+use std::{collections::HashMap, rc::Rc};
+
+/// An interned name, variables, methods and fields are looked up by it instead of by string.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Symbol(u32);
+
+impl Symbol {
+    pub const THIS: Symbol = Symbol(0);
+    pub const SUPER: Symbol = Symbol(1);
+    pub const INIT: Symbol = Symbol(2);
+}
+
+/// Hands out one `Symbol` per distinct name. Every interner starts with `this`, `super`
+/// and `init` so the constants on `Symbol` hold for all of them.
+#[derive(Debug, Clone)]
+pub struct Interner {
+    symbols: HashMap<Rc<str>, Symbol>,
+    names: Vec<Rc<String>>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        let mut interner = Interner {
+            symbols: HashMap::new(),
+            names: Vec::new(),
+        };
+        for name in ["this", "super", "init"] {
+            interner.intern(name);
+        }
+        interner
+    }
+
+    pub fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(symbol) = self.symbols.get(name) {
+            return *symbol;
+        }
+
+        let symbol = Symbol(self.names.len() as u32);
+        self.symbols.insert(Rc::from(name), symbol);
+        self.names.push(Rc::new(name.to_string()));
+        symbol
+    }
+
+    pub fn name(&self, symbol: Symbol) -> &Rc<String> {
+        &self.names[symbol.0 as usize]
+    }
+}
+
+impl Default for Interner {
+    fn default() -> Self {
+        Interner::new()
+    }
+}
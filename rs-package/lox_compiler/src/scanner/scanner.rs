@@ -1,31 +1,104 @@
-use super::tokens::{init_tokens, Token, TokensType, ValueType};
+use super::interner::{Interner, Symbol};
+use super::tokens::{init_tokens, Span, Token, TokensType, ValueType};
 
 use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
 use std::rc::Rc;
 use std::str::Chars;
 
 #[derive(Debug, Clone)]
 pub struct Error {
-    pub line: u8,
-    pub column: u8,
+    pub line: usize,
+    pub column: usize,
+    /// Width of the offending lexeme in characters, diagnostics underline at least one.
+    pub length: usize,
+    /// Stable identifier of the kind of error, see the "Error codes" section of the README.
+    pub code: &'static str,
     pub message: String,
+    pub notes: Vec<String>,
+    /// Lox call frames active when a runtime error was raised, innermost first.
+    pub backtrace: Vec<TraceFrame>,
+}
+
+/// A function on the call stack and the line it was executing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceFrame {
+    pub name: Rc<String>,
+    pub line: usize,
+}
+
+impl fmt::Display for TraceFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "at {} (line {})", self.name, self.line)
+    }
+}
+
+impl Error {
+    pub fn new(line: usize, column: usize, code: &'static str, message: String) -> Self {
+        Error {
+            line,
+            column,
+            length: 1,
+            code,
+            message,
+            notes: Vec::new(),
+            backtrace: Vec::new(),
+        }
+    }
+
+    /// An error at `token`, diagnostics underline its whole lexeme.
+    pub fn at(token: &Token, code: &'static str, message: String) -> Self {
+        Error {
+            length: token.lexeme.chars().count(),
+            ..Error::new(token.line, token.column, code, message)
+        }
+    }
+
+    pub fn with_note(mut self, note: &str) -> Self {
+        self.notes.push(note.to_string());
+        self
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} in line {} column {}",
+            self.message, self.line, self.column
+        )
+    }
+}
+
+/// What starts a line comment, which decides whether `//` is floor division.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CommentSyntax {
+    /// `//` starts a comment and there is no floor division operator.
+    #[default]
+    DoubleSlash,
+    /// `#` starts a comment, leaving `//` to floor division.
+    Hash,
 }
 
 #[derive(Debug)]
 pub struct Scanner<'a> {
     pub source: Chars<'a>,
     pub tokens: VecDeque<Rc<Token>>,
-    start: u8,
-    current: u8,
-    line: u8,
+    start: usize,
+    current: usize,
+    line: usize,
+    start_line: usize,
+    offset: usize,
+    start_offset: usize,
     peeked: VecDeque<char>,
     token_map: BTreeMap<&'a str, TokensType>,
-    errors: Vec<Error>,
-    lexeme_cache: BTreeMap<Rc<String>, Rc<String>>,
+    pub errors: Vec<Error>,
+    pub interner: Interner,
+    pub comments: CommentSyntax,
 }
 
 impl<'a> Scanner<'a> {
-    pub fn new(source: &'a String) -> Self {
+    pub fn new(source: &'a str) -> Self {
         let mut peeked: VecDeque<char> = VecDeque::with_capacity(4);
 
         for source_char in source.chars() {
@@ -38,38 +111,40 @@ impl<'a> Scanner<'a> {
             start: 1,
             current: 1,
             line: 1,
+            start_line: 1,
+            offset: 0,
+            start_offset: 0,
             peeked,
             token_map: init_tokens(),
             errors: Vec::new(),
-            lexeme_cache: BTreeMap::new(),
+            interner: Interner::new(),
+            comments: CommentSyntax::default(),
+        }
+    }
+
+    /// Interns into an existing interner so names keep their symbols across sources.
+    pub fn with_interner(source: &'a str, interner: Interner) -> Self {
+        Scanner {
+            interner,
+            ..Scanner::new(source)
         }
     }
 
-    pub fn scan(&mut self) {
+    pub fn scan(&mut self) -> Result<(), Vec<Error>> {
         loop {
             self.start = self.current;
+            self.start_line = self.line;
+            self.start_offset = self.offset;
             if !self.scan_tokens() {
                 break;
             }
         }
         self.add_token(TokensType::Eof, String::from(""), None);
 
-        if self.errors.len() != 0 {
-            let errors = self
-                .errors
-                .iter()
-                .map(|err| {
-                    format!(
-                        "{} in line {} column {} \n",
-                        err.message, err.line, err.column
-                    )
-                })
-                .collect::<String>();
-            panic!(
-                "\n\n******\nOops! scan tokens errors:\n{}******\n\n",
-                errors
-            );
+        if !self.errors.is_empty() {
+            return Err(self.errors.clone());
         }
+        Ok(())
     }
 
     fn scan_tokens(&mut self) -> bool {
@@ -81,12 +156,27 @@ impl<'a> Scanner<'a> {
                     ')' => self.add_token(TokensType::RightParen, code.to_string(), None),
                     '{' => self.add_token(TokensType::LeftBrace, code.to_string(), None),
                     '}' => self.add_token(TokensType::RightBrace, code.to_string(), None),
+                    '[' => self.add_token(TokensType::LeftBracket, code.to_string(), None),
+                    ']' => self.add_token(TokensType::RightBracket, code.to_string(), None),
                     ',' => self.add_token(TokensType::Comma, code.to_string(), None),
+                    ':' => self.add_token(TokensType::Colon, code.to_string(), None),
                     '.' => self.add_token(TokensType::Dot, code.to_string(), None),
                     '-' => self.add_token(TokensType::Minus, code.to_string(), None),
                     '+' => self.add_token(TokensType::Plus, code.to_string(), None),
                     ';' => self.add_token(TokensType::Semicolon, code.to_string(), None),
-                    '*' => self.add_token(TokensType::Star, code.to_string(), None),
+                    '*' => {
+                        if self.match_char('*') {
+                            let c = self.advance().unwrap();
+                            self.add_token(
+                                TokensType::StarStar,
+                                code.to_string() + &*c.to_string(),
+                                None,
+                            );
+                        } else {
+                            self.add_token(TokensType::Star, code.to_string(), None);
+                        }
+                    }
+                    '%' => self.add_token(TokensType::Percent, code.to_string(), None),
                     '!' => {
                         if self.match_char('=') {
                             let c = self.advance().unwrap();
@@ -136,25 +226,20 @@ impl<'a> Scanner<'a> {
                         }
                     }
                     '/' => {
-                        if self.match_char('/') {
-                            'comment: loop {
-                                let peek = self.peek();
-                                match peek {
-                                    Some(c) => {
-                                        if c == '\n' {
-                                            break 'comment;
-                                        }
-                                        self.advance();
-                                    }
-                                    _ => {
-                                        break 'comment;
-                                    }
-                                }
-                            }
-                        } else {
+                        if !self.match_char('/') {
                             self.add_token(TokensType::Slash, code.to_string(), None);
+                        } else if self.comments == CommentSyntax::Hash {
+                            let c = self.advance().unwrap();
+                            self.add_token(
+                                TokensType::SlashSlash,
+                                code.to_string() + &*c.to_string(),
+                                None,
+                            );
+                        } else {
+                            self.skip_comment();
                         }
                     }
+                    '#' if self.comments == CommentSyntax::Hash => self.skip_comment(),
                     ' ' | '\r' | '\t' => {}
                     '\n' => {
                         self.line += 1;
@@ -165,12 +250,12 @@ impl<'a> Scanner<'a> {
                     'a'..='z' | 'A'..='Z' | '\u{4E00}'..='\u{9FA5}' | '_' => {
                         self.handle_alpha(code)
                     }
-                    _ => self.errors.push(Error {
-                        line: self.line,
-                        //TODO:
-                        column: self.start,
-                        message: String::from("Unexpected character"),
-                    }),
+                    _ => self.errors.push(Error::new(
+                        self.start_line,
+                        self.start,
+                        "E0001",
+                        format!("Unexpected character {:?}", code),
+                    )),
                 }
                 true
             }
@@ -178,32 +263,44 @@ impl<'a> Scanner<'a> {
         }
     }
 
+    fn skip_comment(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == '\n' {
+                break;
+            }
+            self.advance();
+        }
+    }
+
     fn advance(&mut self) -> Option<char> {
         let front = self.peeked.pop_front();
-        if let Some(_) = front {
+        if let Some(c) = front {
             self.current += 1;
+            self.offset += c.len_utf8();
         }
         front
     }
 
     fn add_token(&mut self, token_type: TokensType, lexeme: String, literal: Option<ValueType>) {
-        let lexeme_name;
-
-        if self.lexeme_cache.contains_key(&lexeme) {
-            lexeme_name = self.lexeme_cache.get(&lexeme).unwrap().clone();
-        } else {
-            lexeme_name = Rc::new(lexeme);
-
-            self.lexeme_cache
-                .insert(lexeme_name.clone(), lexeme_name.clone());
-        }
+        let lexeme_symbol = self.interner.intern(&lexeme);
+        // Keywords map to the well-known symbols whatever their spelling in the keyword table.
+        let symbol = match token_type {
+            TokensType::This => Symbol::THIS,
+            TokensType::Super => Symbol::SUPER,
+            _ => lexeme_symbol,
+        };
 
         let token = Token {
             token_type,
-            lexeme: lexeme_name,
+            lexeme: self.interner.name(lexeme_symbol).clone(),
+            symbol,
             literal,
-            line: self.line,
+            line: self.start_line,
             column: self.start,
+            span: Span {
+                start: self.start_offset,
+                end: self.offset,
+            },
         };
         self.tokens.push_back(Rc::new(token));
     }
@@ -238,22 +335,27 @@ impl<'a> Scanner<'a> {
                 match self.peek() {
                     Some(c) => {
                         if c != code {
+                            let s = &*self.advance().unwrap().to_string();
+                            lox_string += s;
                             if c == '\n' {
                                 self.line += 1;
+                                self.current = 1;
                             }
-                            let s = &*self.advance().unwrap().to_string();
-                            lox_string += s;
                         } else {
                             self.advance();
                             break;
                         }
                     }
                     _ => {
-                        self.errors.push(Error {
-                            line: self.line,
-                            column: self.start,
-                            message: String::from("Unterminated string"),
-                        });
+                        self.errors.push(
+                            Error::new(
+                                self.start_line,
+                                self.start,
+                                "E0002",
+                                String::from("Unterminated string"),
+                            )
+                            .with_note(&format!("help: close the string with a matching {}", code)),
+                        );
                         break;
                     }
                 }
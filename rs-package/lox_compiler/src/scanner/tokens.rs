@@ -1,3 +1,4 @@
+use super::interner::Symbol;
 use std::collections::BTreeMap;
 use std::rc::Rc;
 
@@ -8,13 +9,17 @@ pub enum TokensType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
+    Colon,
     Dot,
     Minus,
     Plus,
     Semicolon,
     Slash,
     Star,
+    Percent,
 
     // One or two character tokens.
     Bang,
@@ -25,6 +30,8 @@ pub enum TokensType {
     GreaterEqual,
     Less,
     LessEqual,
+    StarStar,
+    SlashSlash,
 
     // Literals.
     Identifier,
@@ -33,7 +40,9 @@ pub enum TokensType {
 
     // Keywords.
     And,
+    Break,
     Class,
+    Continue,
     Else,
     False,
     Fun,
@@ -59,12 +68,21 @@ pub enum ValueType {
     Bool(bool),
 }
 
+/// Byte offsets of a token in the source, `end` is exclusive.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
 #[derive(Debug, Clone)]
 pub struct Token {
     pub token_type: TokensType,
     pub lexeme: Rc<String>,
-    pub line: u8,
-    pub column: u8,
+    pub symbol: Symbol,
+    pub line: usize,
+    pub column: usize,
+    pub span: Span,
     pub literal: Option<ValueType>,
 }
 
@@ -98,7 +116,9 @@ macro_rules! map {
 pub fn init_tokens<'a>() -> BTreeMap<&'a str, TokensType> {
     map_negative! {
         TokensType::And => "and",
+        TokensType::Break => "break",
         TokensType::Class => "class",
+        TokensType::Continue => "continue",
         TokensType::Else => "else",
         TokensType::False => "false",
         TokensType::Fun => "fun",
@@ -120,7 +140,9 @@ pub fn init_tokens<'a>() -> BTreeMap<&'a str, TokensType> {
 pub fn init_tokens<'a>() -> BTreeMap<&'a str, TokensType> {
     map_negative! {
         TokensType::And => "与上",
+        TokensType::Break => "跳出",
         TokensType::Class => "类",
+        TokensType::Continue => "继续",
         TokensType::Else => "否则",
         TokensType::False => "假值",
         TokensType::Fun => "函数",
@@ -1,16 +1,9 @@
-use crate::map;
-use crate::utils::utils::get_rc_ref_address;
-
 use super::super::parser::{expression::*, statement::*};
-use super::super::scanner::{scanner::*, tokens::*};
+use super::super::scanner::{interner::Symbol, scanner::*, tokens::*};
 
 use std::collections::BTreeMap;
 
-use std::{cell::RefCell, rc::Rc};
-
-pub static THIS_STRING: &'static str = "this";
-pub static SUPER_STRING: &'static str = "super";
-pub static INIT_STRING: &'static str = "init";
+use std::rc::Rc;
 
 #[derive(Debug, Copy, Clone)]
 pub enum FunctionType {
@@ -27,11 +20,76 @@ pub enum ClassType {
     SubClass,
 }
 
+/// A resolved local, `depth` scopes up from the use at index `slot` of that scope.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ScopeSlot {
+    pub depth: usize,
+    pub slot: usize,
+}
+
+/// Resolved locals indexed by the `NodeId` of the variable, assignment, `this` or `super`
+/// expression, globals have no entry.
+#[derive(Debug, Clone, Default)]
+pub struct ScopeRecord {
+    slots: Vec<Option<ScopeSlot>>,
+}
+
+impl ScopeRecord {
+    pub fn get(&self, id: NodeId) -> Option<ScopeSlot> {
+        self.slots.get(id).copied().flatten()
+    }
+
+    pub fn insert(&mut self, id: NodeId, scope_slot: ScopeSlot) {
+        if self.slots.len() <= id {
+            self.slots.resize(id + 1, None);
+        }
+        self.slots[id] = Some(scope_slot);
+    }
+
+    /// Merges the record of a later input, ids of different inputs never overlap.
+    pub fn extend(&mut self, other: &ScopeRecord) {
+        for (id, scope_slot) in other.slots.iter().enumerate() {
+            if let Some(scope_slot) = scope_slot {
+                self.insert(id, *scope_slot);
+            }
+        }
+    }
+}
+
+/// Slots are handed out in declaration order, which is the order the interpreter
+/// defines the values at runtime.
+#[derive(Debug, Clone, Default)]
+pub struct Scope {
+    variables: BTreeMap<Symbol, (usize, bool)>,
+    slot_count: usize,
+}
+
+impl Scope {
+    fn with_defined(name: Symbol) -> Self {
+        let mut scope = Scope::default();
+        scope.declare(name);
+        scope.define(name);
+        scope
+    }
+
+    fn declare(&mut self, name: Symbol) {
+        // A redeclaration is reported by the analyst but still gets its own slot.
+        self.variables.insert(name, (self.slot_count, false));
+        self.slot_count += 1;
+    }
+
+    fn define(&mut self, name: Symbol) {
+        if let Some((_, defined)) = self.variables.get_mut(&name) {
+            *defined = true;
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ScopeAnalyst {
     pub statements: Rc<Vec<Stmt>>,
-    pub scopes: Vec<BTreeMap<*const u8, bool>>,
-    pub scope_record: Rc<RefCell<BTreeMap<usize, usize>>>,
+    pub scopes: Vec<Scope>,
+    pub scope_record: ScopeRecord,
     pub function_type: FunctionType,
     pub class_type: ClassType,
     pub errors: Vec<Error>,
@@ -42,15 +100,20 @@ impl ScopeAnalyst {
         ScopeAnalyst {
             statements,
             scopes: Vec::new(),
-            scope_record: Rc::new(RefCell::new(BTreeMap::new())),
+            scope_record: ScopeRecord::default(),
             function_type: FunctionType::None,
             class_type: ClassType::None,
             errors: Vec::new(),
         }
     }
 
-    pub fn analysis(&mut self) {
+    pub fn analysis(&mut self) -> Result<(), Vec<Error>> {
         self.evaluate_statement_list(&self.statements.clone());
+
+        if !self.errors.is_empty() {
+            return Err(self.errors.clone());
+        }
+        Ok(())
     }
 
     fn evaluate_statement_list(&mut self, stmts: &Vec<Stmt>) {
@@ -70,6 +133,7 @@ impl ScopeAnalyst {
             Stmt::Var(stmt_var) => self.visit_var_stmt(stmt_var),
             Stmt::Block(stmt_block) => self.visit_block_stmt(stmt_block),
             Stmt::Return(stmt_return) => self.visit_return_stmt(stmt_return),
+            Stmt::Break(_) | Stmt::Continue(_) => {}
             Stmt::Class(stmt_class) => self.visit_class_stmt(stmt_class),
         }
     }
@@ -94,6 +158,15 @@ impl ScopeAnalyst {
             Expr::Set(expr_set) => self.visit_set_expr(expr_set),
             Expr::This(expr_this) => self.visit_this_expr(expr_this),
             Expr::Super(expr_super) => self.visit_super_expr(expr_super),
+            Expr::List(expr_list) => self.evaluate_expression_list(&expr_list.elements),
+            Expr::Map(expr_map) => {
+                for (key, value) in expr_map.entries.iter() {
+                    self.evaluate_expression_item(key);
+                    self.evaluate_expression_item(value);
+                }
+            }
+            Expr::Index(expr_index) => self.visit_index_expr(expr_index),
+            Expr::IndexSet(expr_index_set) => self.visit_index_set_expr(expr_index_set),
         }
     }
 
@@ -126,7 +199,7 @@ impl ScopeAnalyst {
     }
 
     fn visit_for_stmt(&mut self, stmt: &ForStatement) {
-        self.scopes.push(BTreeMap::new());
+        self.scopes.push(Scope::default());
         if let Some(initializer) = &stmt.initializer {
             self.evaluate_statement_item(initializer);
         }
@@ -149,7 +222,7 @@ impl ScopeAnalyst {
     }
 
     fn visit_block_stmt(&mut self, stmt: &BlockStatement) {
-        self.scopes.push(BTreeMap::new());
+        self.scopes.push(Scope::default());
         self.evaluate_statement_list(&stmt.statements);
         self.scopes.pop();
     }
@@ -157,41 +230,31 @@ impl ScopeAnalyst {
     fn visit_return_stmt(&mut self, stmt: &ReturnStatement) {
         match self.function_type {
             FunctionType::None => {
-                self.errors.push(Error {
-                    line: stmt.keyword.line,
-                    column: stmt.keyword.column,
-                    message: String::from("Can't return from top-level code"),
-                });
+                self.errors.push(
+                    Error::at(
+                        &stmt.keyword,
+                        "E0202",
+                        String::from("Can't return from top-level code"),
+                    )
+                    .with_note("note: \"return\" is only allowed inside functions and methods"),
+                );
             }
             _ => {}
         }
 
-        let mut closure = || {
-            match self.function_type {
-                FunctionType::Initializer => {
-                    self.errors.push(Error {
-                        line: stmt.keyword.line,
-                        column: stmt.keyword.column,
-                        message: String::from("Can't use return a value from an initializer"),
-                    });
-                }
-                _ => {}
-            }
-            self.evaluate_expression_item(&stmt.value);
-        };
-
-        match &stmt.value {
-            Expr::Literal(literal_expr) => {
-                if let Some(_) = &literal_expr.value {
-                    closure();
-                }
-            }
-            _ => {
-                closure();
+        if let Some(value) = &stmt.value {
+            if let FunctionType::Initializer = self.function_type {
+                self.errors.push(
+                    Error::at(
+                        &stmt.keyword,
+                        "E0203",
+                        String::from("Can't return a value from an initializer"),
+                    )
+                    .with_note("note: \"init\" always returns the new instance"),
+                );
             }
+            self.evaluate_expression_item(value);
         }
-
-        self.evaluate_expression_item(&stmt.value);
     }
 
     fn visit_class_stmt(&mut self, stmt: &ClassStatement) {
@@ -203,27 +266,27 @@ impl ScopeAnalyst {
             match superclass {
                 Expr::Variable(superclass_expr) => {
                     if stmt.name.lexeme == superclass_expr.name.lexeme {
-                        self.errors.push(Error {
-                            line: stmt.name.line,
-                            column: stmt.name.column,
-                            message: format!(
-                                r#"A class can't inherit from itself("{:?}""#,
+                        self.errors.push(Error::at(
+                            &stmt.name,
+                            "E0207",
+                            format!(
+                                r#"A class can't inherit from itself("{}")"#,
                                 stmt.name.lexeme
                             ),
-                        })
+                        ))
                     }
                 }
                 _ => {}
             }
             self.evaluate_expression_item(superclass);
-            self.scopes.push(map! {SUPER_STRING.as_ptr() => true});
+            self.scopes.push(Scope::with_defined(Symbol::SUPER));
             self.class_type = ClassType::SubClass;
         }
 
-        self.scopes.push(map! {THIS_STRING.as_ptr() => true});
+        self.scopes.push(Scope::with_defined(Symbol::THIS));
 
         for method in stmt.methods.iter() {
-            if *method.name.lexeme == "init" {
+            if method.name.symbol == Symbol::INIT {
                 self.evaluate_function(method, FunctionType::Initializer);
             } else {
                 self.evaluate_function(method, FunctionType::Method)
@@ -260,29 +323,30 @@ impl ScopeAnalyst {
     fn visit_variable_expr(&mut self, expr: &Rc<VariableExpression>) {
         if self.scopes.len() != 0 {
             let last = self.scopes.last().unwrap();
-            if let Some(flag) = last.get(&ScopeAnalyst::get_scope_key_name(&expr.name.lexeme)) {
-                if !flag {
-                    self.errors.push(Error {
-                        line: expr.name.line,
-                        column: expr.name.column,
-                        message: format!(
-                            r#"Can't read local variable in its own initializer("{}")"#,
-                            expr.name.lexeme
-                        ),
-                    });
+            if let Some((_, defined)) = last.variables.get(&expr.name.symbol) {
+                if !defined {
+                    self.errors.push(
+                        Error::at(
+                            &expr.name,
+                            "E0200",
+                            format!(
+                                r#"Can't read local variable in its own initializer("{}")"#,
+                                expr.name.lexeme
+                            ),
+                        )
+                        .with_note("help: rename the new variable if you meant the outer one"),
+                    );
                 }
             }
         }
 
-        let add = get_rc_ref_address(expr.clone());
-        self.calculate(add, &expr.name);
+        self.calculate(expr.id, &expr.name);
     }
 
     fn visit_assignment_expr(&mut self, expr: &Rc<AssignmentExpression>) {
         self.evaluate_expression_item(&expr.value);
 
-        let add = get_rc_ref_address(expr.clone());
-        self.calculate(add, &expr.name);
+        self.calculate(expr.id, &expr.name);
     }
 
     fn visit_call_expr(&mut self, expr: &CallExpression) {
@@ -299,43 +363,57 @@ impl ScopeAnalyst {
         self.evaluate_expression_item(&expr.object);
     }
 
+    fn visit_index_expr(&mut self, expr: &IndexExpression) {
+        self.evaluate_expression_item(&expr.object);
+        self.evaluate_expression_item(&expr.index);
+    }
+
+    fn visit_index_set_expr(&mut self, expr: &IndexSetExpression) {
+        self.evaluate_expression_item(&expr.value);
+        self.evaluate_expression_item(&expr.object);
+        self.evaluate_expression_item(&expr.index);
+    }
+
     fn visit_this_expr(&mut self, expr: &Rc<ThisExpression>) {
         match self.class_type {
-            ClassType::None => self.errors.push(Error {
-                line: expr.keyword.line,
-                column: expr.keyword.column,
-                message: String::from(r#"Can't use "this" outside of a class"#),
-            }),
+            ClassType::None => self.errors.push(Error::at(
+                &expr.keyword,
+                "E0204",
+                String::from(r#"Can't use "this" outside of a class"#),
+            )),
             _ => {}
         }
 
-        let add = get_rc_ref_address(expr.clone());
-        self.calculate(add, &expr.keyword);
+        self.calculate(expr.id, &expr.keyword);
     }
 
     fn visit_super_expr(&mut self, expr: &Rc<SuperExpression>) {
         match self.class_type {
-            ClassType::None => self.errors.push(Error {
-                line: expr.keyword.line,
-                column: expr.keyword.column,
-                message: String::from(r#"Can't use "super" outside of a class"#),
-            }),
-            ClassType::Class => self.errors.push(Error {
-                line: expr.keyword.line,
-                column: expr.keyword.column,
-                message: String::from(r#"Can't use "super" in a class with no superclass"#),
-            }),
+            ClassType::None => self.errors.push(Error::at(
+                &expr.keyword,
+                "E0205",
+                String::from(r#"Can't use "super" outside of a class"#),
+            )),
+            ClassType::Class => self.errors.push(Error::at(
+                &expr.keyword,
+                "E0206",
+                String::from(r#"Can't use "super" in a class with no superclass"#),
+            )),
             _ => {}
         }
 
-        let add = get_rc_ref_address(expr.clone());
-        self.calculate(add, &expr.keyword);
+        self.calculate(expr.id, &expr.keyword);
     }
 
-    fn calculate(&mut self, address: usize, token: &Token) {
+    fn calculate(&mut self, id: NodeId, token: &Token) {
         for (pos, scope) in self.scopes.iter().rev().enumerate() {
-            if scope.contains_key(&ScopeAnalyst::get_scope_key_name(&token.lexeme)) {
-                self.scope_record.borrow_mut().insert(address, pos);
+            if let Some((slot, _)) = scope.variables.get(&token.symbol) {
+                let scope_slot = ScopeSlot {
+                    depth: pos,
+                    slot: *slot,
+                };
+                self.scope_record.insert(id, scope_slot);
+                return;
             }
         }
     }
@@ -343,14 +421,24 @@ impl ScopeAnalyst {
     fn declare(&mut self, name: &Token) {
         if self.scopes.len() != 0 {
             let last = self.scopes.last_mut().unwrap();
-            last.insert(ScopeAnalyst::get_scope_key_name(&name.lexeme), false);
+            if last.variables.contains_key(&name.symbol) {
+                self.errors.push(Error::at(
+                    name,
+                    "E0201",
+                    format!(
+                        r#"Already a variable with this name in this scope("{}")"#,
+                        name.lexeme
+                    ),
+                ));
+            }
+            last.declare(name.symbol);
         }
     }
 
     fn define(&mut self, name: &Token) {
         if self.scopes.len() != 0 {
             let last = self.scopes.last_mut().unwrap();
-            last.insert(ScopeAnalyst::get_scope_key_name(&name.lexeme), true);
+            last.define(name.symbol);
         }
     }
 
@@ -358,7 +446,7 @@ impl ScopeAnalyst {
         let previous_function_type = self.function_type;
         self.function_type = function_type;
 
-        self.scopes.push(BTreeMap::new());
+        self.scopes.push(Scope::default());
 
         for statement in &stmt.params {
             self.declare(statement);
@@ -370,19 +458,4 @@ impl ScopeAnalyst {
 
         self.function_type = previous_function_type;
     }
-
-    pub fn get_scope_key_name(lexeme: &Rc<String>) -> *const u8 {
-        if **lexeme == INIT_STRING {
-            return INIT_STRING.as_ptr();
-        }
-
-        if **lexeme == THIS_STRING {
-            return THIS_STRING.as_ptr();
-        }
-
-        if **lexeme == SUPER_STRING {
-            return SUPER_STRING.as_ptr();
-        }
-        return lexeme.as_ptr();
-    }
 }
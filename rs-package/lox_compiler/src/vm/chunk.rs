@@ -0,0 +1,106 @@
+use crate::environment::environment_value::EnvironmentValue;
+use crate::scanner::{interner::Symbol, tokens::Token};
+use crate::vm::object::VmFunction;
+
+use std::rc::Rc;
+
+/// Instructions of the stack vm, jump operands are absolute instruction indexes.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum OpCode {
+    Constant(u32),
+    Nil,
+    True,
+    False,
+    Pop,
+    GetLocal(u32),
+    SetLocal(u32),
+    GetGlobal(u32),
+    DefineGlobal(u32),
+    SetGlobal(u32),
+    GetUpvalue(u32),
+    SetUpvalue(u32),
+    /// Like `SetLocal`, `SetGlobal` and `SetUpvalue` but pops the value, for assignments
+    /// whose value is discarded.
+    StoreLocal(u32),
+    StoreGlobal(u32),
+    StoreUpvalue(u32),
+    GetProperty(u32),
+    SetProperty(u32),
+    GetSuper(u32),
+    /// Builds a list of the given number of values on top of the stack.
+    BuildList(u32),
+    /// Builds a map of the given number of key and value pairs on top of the stack.
+    BuildMap(u32),
+    GetIndex,
+    SetIndex,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    FloorDivide,
+    Power,
+    Not,
+    Negate,
+    Print,
+    Jump(u32),
+    JumpIfFalse(u32),
+    /// Pops the condition and jumps if it is falsy.
+    PopJumpIfFalse(u32),
+    /// Jumps back to the start of a loop, counting a step against the execution limits.
+    Loop(u32),
+    Call(u32),
+    Closure(u32),
+    CloseUpvalue,
+    Return,
+    Class(u32),
+    Inherit,
+    Method(u32),
+}
+
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<OpCode>,
+    /// Line, column and width of the token each instruction was compiled from.
+    pub positions: Vec<(usize, usize, usize)>,
+    pub constants: Vec<EnvironmentValue>,
+    /// Property, method and class names, the lexeme is kept for error messages.
+    pub names: Vec<(Symbol, Rc<String>)>,
+    pub functions: Vec<Rc<VmFunction>>,
+}
+
+impl Chunk {
+    pub fn write(&mut self, op: OpCode, position: (usize, usize, usize)) -> usize {
+        self.code.push(op);
+        self.positions.push(position);
+        self.code.len() - 1
+    }
+
+    pub fn add_constant(&mut self, value: EnvironmentValue) -> u32 {
+        self.constants.push(value);
+        (self.constants.len() - 1) as u32
+    }
+
+    pub fn add_name(&mut self, name: &Token) -> u32 {
+        if let Some(pos) = self
+            .names
+            .iter()
+            .position(|(symbol, _)| *symbol == name.symbol)
+        {
+            return pos as u32;
+        }
+        self.names.push((name.symbol, name.lexeme.clone()));
+        (self.names.len() - 1) as u32
+    }
+
+    pub fn add_function(&mut self, function: VmFunction) -> u32 {
+        self.functions.push(Rc::new(function));
+        (self.functions.len() - 1) as u32
+    }
+}
@@ -0,0 +1,718 @@
+use crate::environment::environment_value::EnvironmentValue;
+use crate::parser::{expression::*, statement::*};
+use crate::scanner::{interner::Symbol, tokens::*};
+use crate::semantic::scope_analyst::{ScopeRecord, ScopeSlot};
+use crate::vm::chunk::{Chunk, OpCode};
+use crate::vm::machine::Globals;
+use crate::vm::object::*;
+
+use std::rc::Rc;
+
+#[derive(Debug)]
+struct Local {
+    depth: usize,
+    is_captured: bool,
+}
+
+/// A scope of the resolver, holding the stack slot in the function at `level` of each
+/// variable it declares, in declaration order. `ScopeSlot`s index into these.
+#[derive(Debug)]
+struct ResolvedScope {
+    level: usize,
+    slots: Vec<u32>,
+}
+
+/// Where a variable lives once resolved.
+#[derive(Debug, Copy, Clone)]
+enum Access {
+    Local(u32),
+    Upvalue(u32),
+    Global(u32),
+}
+
+/// Jumps out of a loop waiting for the positions they land on.
+#[derive(Debug)]
+struct LoopState {
+    /// Scope depth outside the loop body, locals deeper than it are dropped before a jump.
+    scope_depth: usize,
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+#[derive(Debug)]
+struct FunctionState {
+    name: Rc<String>,
+    arity: usize,
+    kind: FunctionKind,
+    chunk: Chunk,
+    locals: Vec<Local>,
+    upvalues: Vec<UpvalueDescriptor>,
+    scope_depth: usize,
+    loops: Vec<LoopState>,
+}
+
+impl FunctionState {
+    fn new(name: Rc<String>, kind: FunctionKind) -> Self {
+        FunctionState {
+            name,
+            arity: 0,
+            kind,
+            chunk: Chunk::default(),
+            // Slot zero holds the callee, or the receiver inside methods.
+            locals: vec![Local {
+                depth: 0,
+                is_captured: false,
+            }],
+            upvalues: Vec::new(),
+            scope_depth: 0,
+            loops: Vec::new(),
+        }
+    }
+}
+
+/// Compiles resolved statements into bytecode, turning the resolver's slots into stack
+/// slots and upvalues the way clox does.
+#[derive(Debug)]
+pub struct Compiler<'a> {
+    states: Vec<FunctionState>,
+    scopes: Vec<ResolvedScope>,
+    scope_record: &'a ScopeRecord,
+    globals: &'a mut Globals,
+    position: (usize, usize, usize),
+}
+
+impl<'a> Compiler<'a> {
+    pub fn new(globals: &'a mut Globals, scope_record: &'a ScopeRecord) -> Self {
+        Compiler {
+            states: vec![FunctionState::new(
+                Rc::new(String::from("script")),
+                FunctionKind::Script,
+            )],
+            scopes: Vec::new(),
+            scope_record,
+            globals,
+            position: (0, 0, 0),
+        }
+    }
+
+    /// With `echo`, a trailing expression statement becomes the value returned by the script.
+    pub fn compile(mut self, statements: &[Stmt], echo: bool) -> VmFunction {
+        match statements.split_last() {
+            Some((Stmt::Expression(last), rest)) if echo => {
+                self.statement_list(rest);
+                self.expression(&last.expression);
+                self.emit(OpCode::Return);
+            }
+            _ => {
+                self.statement_list(statements);
+                self.emit(OpCode::Nil);
+                self.emit(OpCode::Return);
+            }
+        }
+        let state = self.states.pop().unwrap();
+        VmFunction {
+            name: state.name,
+            arity: state.arity,
+            kind: state.kind,
+            chunk: state.chunk,
+            upvalues: state.upvalues,
+        }
+    }
+
+    fn state(&mut self) -> &mut FunctionState {
+        self.states.last_mut().unwrap()
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.state().chunk
+    }
+
+    fn at(&mut self, token: &Token) {
+        self.position = (token.line, token.column, token.lexeme.chars().count());
+    }
+
+    fn emit(&mut self, op: OpCode) -> usize {
+        let position = self.position;
+        self.chunk().write(op, position)
+    }
+
+    fn emit_jump(&mut self, op: fn(u32) -> OpCode) -> usize {
+        self.emit(op(u32::MAX))
+    }
+
+    fn patch_jump(&mut self, pos: usize) {
+        let target = self.chunk().code.len() as u32;
+        let chunk = self.chunk();
+        chunk.code[pos] = match chunk.code[pos] {
+            OpCode::Jump(_) => OpCode::Jump(target),
+            OpCode::JumpIfFalse(_) => OpCode::JumpIfFalse(target),
+            OpCode::PopJumpIfFalse(_) => OpCode::PopJumpIfFalse(target),
+            op => op,
+        };
+    }
+
+    fn current_pos(&mut self) -> u32 {
+        self.chunk().code.len() as u32
+    }
+
+    fn begin_scope(&mut self) {
+        self.state().scope_depth += 1;
+        self.scopes.push(ResolvedScope {
+            level: self.states.len() - 1,
+            slots: Vec::new(),
+        });
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+        self.state().scope_depth -= 1;
+        let depth = self.state().scope_depth;
+
+        let count = self.discard_locals(depth);
+        let locals = &mut self.state().locals;
+        locals.truncate(locals.len() - count);
+    }
+
+    /// Emits the pops of the locals deeper than `depth`, returning how many there are. They
+    /// stay declared, so code after a jump out of their scope can still be compiled.
+    fn discard_locals(&mut self, depth: usize) -> usize {
+        let captured = self
+            .state()
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth > depth)
+            .map(|local| local.is_captured)
+            .collect::<Vec<_>>();
+
+        for is_captured in captured.iter() {
+            if *is_captured {
+                self.emit(OpCode::CloseUpvalue);
+            } else {
+                self.emit(OpCode::Pop);
+            }
+        }
+        captured.len()
+    }
+
+    fn begin_loop(&mut self) {
+        let scope_depth = self.state().scope_depth;
+        self.state().loops.push(LoopState {
+            scope_depth,
+            breaks: Vec::new(),
+            continues: Vec::new(),
+        });
+    }
+
+    fn patch_continues(&mut self) {
+        let continues = std::mem::take(&mut self.state().loops.last_mut().unwrap().continues);
+        for jump in continues {
+            self.patch_jump(jump);
+        }
+    }
+
+    fn end_loop(&mut self) {
+        let state = self.state().loops.pop().unwrap();
+        for jump in state.breaks {
+            self.patch_jump(jump);
+        }
+    }
+
+    /// Leaves the innermost loop body, the jump is patched once the loop is compiled.
+    fn loop_jump(&mut self, keyword: &Token) {
+        let depth = self.state().loops.last().unwrap().scope_depth;
+        self.discard_locals(depth);
+        self.at(keyword);
+        let jump = self.emit_jump(OpCode::Jump);
+
+        let state = self.state().loops.last_mut().unwrap();
+        if keyword.token_type == TokensType::Break {
+            state.breaks.push(jump);
+        } else {
+            state.continues.push(jump);
+        }
+    }
+
+    /// Declares a local in the innermost scope, taking the next stack slot.
+    fn add_local(&mut self) {
+        let state = self.state();
+        let depth = state.scope_depth;
+        state.locals.push(Local {
+            depth,
+            is_captured: false,
+        });
+        let slot = (state.locals.len() - 1) as u32;
+        self.scopes.last_mut().unwrap().slots.push(slot);
+    }
+
+    /// Finds the variable `name` the resolver bound to `scope_slot`, none means a global.
+    fn access(&mut self, scope_slot: Option<ScopeSlot>, name: &Token) -> Access {
+        let scope_slot = match scope_slot {
+            Some(scope_slot) => scope_slot,
+            None => return Access::Global(self.globals.index(name.symbol, &name.lexeme) as u32),
+        };
+
+        let level = self.states.len() - 1;
+        let scope = &self.scopes[self.scopes.len() - 1 - scope_slot.depth];
+        let (owner, slot) = (scope.level, scope.slots[scope_slot.slot]);
+        if owner == level {
+            Access::Local(slot)
+        } else {
+            Access::Upvalue(self.resolve_upvalue(level, owner, slot))
+        }
+    }
+
+    /// Threads the local at `slot` of the function at `owner` through the upvalues of every
+    /// function between it and the one at `level`.
+    fn resolve_upvalue(&mut self, level: usize, owner: usize, slot: u32) -> u32 {
+        if level - 1 == owner {
+            self.states[owner].locals[slot as usize].is_captured = true;
+            return self.add_upvalue(level, true, slot);
+        }
+        let index = self.resolve_upvalue(level - 1, owner, slot);
+        self.add_upvalue(level, false, index)
+    }
+
+    fn add_upvalue(&mut self, level: usize, is_local: bool, index: u32) -> u32 {
+        let upvalues = &mut self.states[level].upvalues;
+        if let Some(pos) = upvalues
+            .iter()
+            .position(|upvalue| upvalue.is_local == is_local && upvalue.index == index)
+        {
+            return pos as u32;
+        }
+        upvalues.push(UpvalueDescriptor { is_local, index });
+        (upvalues.len() - 1) as u32
+    }
+
+    fn get_variable(&mut self, access: Access) {
+        let op = match access {
+            Access::Local(slot) => OpCode::GetLocal(slot),
+            Access::Upvalue(index) => OpCode::GetUpvalue(index),
+            Access::Global(index) => OpCode::GetGlobal(index),
+        };
+        self.emit(op);
+    }
+
+    fn set_variable(&mut self, access: Access) {
+        let op = match access {
+            Access::Local(slot) => OpCode::SetLocal(slot),
+            Access::Upvalue(index) => OpCode::SetUpvalue(index),
+            Access::Global(index) => OpCode::SetGlobal(index),
+        };
+        self.emit(op);
+    }
+
+    /// Same as `set_variable` but the value is popped.
+    fn store_variable(&mut self, access: Access) {
+        let op = match access {
+            Access::Local(slot) => OpCode::StoreLocal(slot),
+            Access::Upvalue(index) => OpCode::StoreUpvalue(index),
+            Access::Global(index) => OpCode::StoreGlobal(index),
+        };
+        self.emit(op);
+    }
+
+    /// Binds the value on top of the stack to `name`, as a global at the top level or a new
+    /// local, and returns where it went.
+    fn define_variable(&mut self, name: &Token) -> Access {
+        if self.state().scope_depth == 0 {
+            self.at(name);
+            let index = self.globals.index(name.symbol, &name.lexeme) as u32;
+            self.emit(OpCode::DefineGlobal(index));
+            Access::Global(index)
+        } else {
+            self.add_local();
+            Access::Local((self.state().locals.len() - 1) as u32)
+        }
+    }
+
+    fn statement_list(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts.iter() {
+            self.statement(stmt);
+        }
+    }
+
+    fn statement(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Function(stmt_function) => self.function_stmt(stmt_function),
+            Stmt::If(stmt_if) => self.if_stmt(stmt_if),
+            Stmt::Expression(stmt_expression) => self.discarded(&stmt_expression.expression),
+            Stmt::Print(stmt_print) => {
+                self.expression(&stmt_print.expression);
+                self.at(&stmt_print.keyword);
+                self.emit(OpCode::Print);
+            }
+            Stmt::While(stmt_while) => self.while_stmt(stmt_while),
+            Stmt::For(stmt_for) => self.for_stmt(stmt_for),
+            Stmt::Var(stmt_var) => self.var_stmt(stmt_var),
+            Stmt::Block(stmt_block) => {
+                self.begin_scope();
+                self.statement_list(&stmt_block.statements);
+                self.end_scope();
+            }
+            Stmt::Return(stmt_return) => self.return_stmt(stmt_return),
+            Stmt::Break(stmt_break) => self.loop_jump(&stmt_break.keyword),
+            Stmt::Continue(stmt_continue) => self.loop_jump(&stmt_continue.keyword),
+            Stmt::Class(stmt_class) => self.class_stmt(stmt_class),
+        }
+    }
+
+    fn function_stmt(&mut self, stmt: &FunctionStatement) {
+        if self.state().scope_depth > 0 {
+            // Declare first so the body can refer to itself.
+            self.add_local();
+            self.function(stmt, FunctionKind::Function);
+        } else {
+            self.function(stmt, FunctionKind::Function);
+            self.define_variable(&stmt.name);
+        }
+    }
+
+    fn function(&mut self, stmt: &FunctionStatement, kind: FunctionKind) {
+        self.states
+            .push(FunctionState::new(stmt.name.lexeme.clone(), kind));
+        let scope_count = self.scopes.len();
+        if kind == FunctionKind::Method || kind == FunctionKind::Initializer {
+            // The resolver's scope of `this`, the receiver in slot zero.
+            self.scopes.push(ResolvedScope {
+                level: self.states.len() - 1,
+                slots: vec![0],
+            });
+        }
+        self.begin_scope();
+
+        self.state().arity = stmt.params.len();
+        for _ in stmt.params.iter() {
+            self.add_local();
+        }
+        self.statement_list(&stmt.body.statements);
+        self.emit_implicit_return();
+
+        self.scopes.truncate(scope_count);
+        let state = self.states.pop().unwrap();
+        let function = VmFunction {
+            name: state.name,
+            arity: state.arity,
+            kind: state.kind,
+            chunk: state.chunk,
+            upvalues: state.upvalues,
+        };
+
+        self.at(&stmt.name);
+        let index = self.chunk().add_function(function);
+        self.emit(OpCode::Closure(index));
+    }
+
+    fn emit_implicit_return(&mut self) {
+        if self.state().kind == FunctionKind::Initializer {
+            self.emit(OpCode::GetLocal(0));
+        } else {
+            self.emit(OpCode::Nil);
+        }
+        self.emit(OpCode::Return);
+    }
+
+    fn if_stmt(&mut self, stmt: &IfStatement) {
+        self.expression(&stmt.condition);
+        let then_jump = self.emit_jump(OpCode::PopJumpIfFalse);
+        self.statement(&stmt.then_branch);
+
+        let else_jump = self.emit_jump(OpCode::Jump);
+        self.patch_jump(then_jump);
+
+        if let Some(else_branch) = &stmt.else_branch {
+            self.statement(else_branch);
+        }
+        self.patch_jump(else_jump);
+    }
+
+    fn while_stmt(&mut self, stmt: &WhileStatement) {
+        let loop_start = self.current_pos();
+        self.expression(&stmt.condition);
+
+        let exit_jump = self.emit_jump(OpCode::PopJumpIfFalse);
+        self.begin_loop();
+        self.statement(&stmt.body);
+        self.patch_continues();
+        self.at(&stmt.keyword);
+        self.emit(OpCode::Loop(loop_start));
+
+        self.patch_jump(exit_jump);
+        self.end_loop();
+    }
+
+    fn for_stmt(&mut self, stmt: &ForStatement) {
+        self.begin_scope();
+        if let Some(initializer) = &stmt.initializer {
+            self.statement(initializer);
+        }
+
+        let loop_start = self.current_pos();
+        let mut exit_jump = None;
+        if let Some(condition) = &stmt.condition {
+            self.expression(condition);
+            exit_jump = Some(self.emit_jump(OpCode::PopJumpIfFalse));
+        }
+
+        self.begin_loop();
+        self.statement(&stmt.body);
+        self.patch_continues();
+
+        if let Some(updator) = &stmt.updator {
+            self.discarded(updator);
+        }
+        self.at(&stmt.keyword);
+        self.emit(OpCode::Loop(loop_start));
+
+        if let Some(exit_jump) = exit_jump {
+            self.patch_jump(exit_jump);
+        }
+        self.end_loop();
+        self.end_scope();
+    }
+
+    /// Compiles an expression whose value is not used, an assignment stores its value
+    /// instead of leaving it to be popped.
+    fn discarded(&mut self, expr: &Expr) {
+        if let Expr::Assignment(expr_assignment) = expr {
+            let access = self.assigned(expr_assignment);
+            self.store_variable(access);
+        } else {
+            self.expression(expr);
+            self.emit(OpCode::Pop);
+        }
+    }
+
+    /// Compiles the value of an assignment and resolves its variable.
+    fn assigned(&mut self, expr: &AssignmentExpression) -> Access {
+        self.expression(&expr.value);
+        let scope_slot = self.scope_record.get(expr.id);
+        let access = self.access(scope_slot, &expr.name);
+        self.at(&expr.name);
+        access
+    }
+
+    fn var_stmt(&mut self, stmt: &VarStatement) {
+        if let Some(initializer) = &stmt.initializer {
+            self.expression(initializer);
+        } else {
+            self.emit(OpCode::Nil);
+        }
+        self.define_variable(&stmt.name);
+    }
+
+    fn return_stmt(&mut self, stmt: &ReturnStatement) {
+        self.at(&stmt.keyword);
+        if let Some(value) = &stmt.value {
+            self.expression(value);
+            self.emit(OpCode::Return);
+        } else {
+            self.emit_implicit_return();
+        }
+    }
+
+    fn class_stmt(&mut self, stmt: &ClassStatement) {
+        self.at(&stmt.name);
+        let name = self.chunk().add_name(&stmt.name);
+        self.emit(OpCode::Class(name));
+        let class = self.define_variable(&stmt.name);
+
+        if let Some(superclass) = &stmt.superclass {
+            self.expression(superclass);
+
+            // The resolver's scope of `super`, the superclass left on the stack.
+            self.begin_scope();
+            self.add_local();
+
+            self.at(&stmt.name);
+            self.get_variable(class);
+            self.emit(OpCode::Inherit);
+        }
+
+        self.at(&stmt.name);
+        self.get_variable(class);
+        for method in stmt.methods.iter() {
+            let kind = if method.name.symbol == Symbol::INIT {
+                FunctionKind::Initializer
+            } else {
+                FunctionKind::Method
+            };
+            self.function(method, kind);
+
+            let name = self.chunk().add_name(&method.name);
+            self.emit(OpCode::Method(name));
+        }
+        self.emit(OpCode::Pop);
+
+        if stmt.superclass.is_some() {
+            self.end_scope();
+        }
+    }
+
+    fn expression(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Binary(expr_binary) => self.binary_expr(expr_binary),
+            Expr::Logical(expr_logical) => self.logical_expr(expr_logical),
+            Expr::Grouping(expr_grouping) => self.expression(&expr_grouping.expression),
+            Expr::Literal(expr_literal) => self.literal_expr(expr_literal),
+            Expr::Unary(expr_unary) => {
+                self.expression(&expr_unary.expression);
+                self.at(&expr_unary.operator);
+                match expr_unary.operator.token_type {
+                    TokensType::Minus => self.emit(OpCode::Negate),
+                    _ => self.emit(OpCode::Not),
+                };
+            }
+            Expr::Variable(expr_variable) => {
+                let scope_slot = self.scope_record.get(expr_variable.id);
+                let access = self.access(scope_slot, &expr_variable.name);
+                self.at(&expr_variable.name);
+                self.get_variable(access);
+            }
+            Expr::Assignment(expr_assignment) => {
+                let access = self.assigned(expr_assignment);
+                self.set_variable(access);
+            }
+            Expr::Call(expr_call) => {
+                self.expression(&expr_call.callee);
+                for arg in expr_call.args.iter() {
+                    self.expression(arg);
+                }
+                self.at(&expr_call.end_parenthese);
+                self.emit(OpCode::Call(expr_call.args.len() as u32));
+            }
+            Expr::Get(expr_get) => {
+                self.expression(&expr_get.object);
+                self.at(&expr_get.name);
+                let name = self.chunk().add_name(&expr_get.name);
+                self.emit(OpCode::GetProperty(name));
+            }
+            Expr::Set(expr_set) => {
+                self.expression(&expr_set.object);
+                self.expression(&expr_set.value);
+                self.at(&expr_set.name);
+                let name = self.chunk().add_name(&expr_set.name);
+                self.emit(OpCode::SetProperty(name));
+            }
+            Expr::List(expr_list) => {
+                for element in expr_list.elements.iter() {
+                    self.expression(element);
+                }
+                self.at(&expr_list.end_bracket);
+                self.emit(OpCode::BuildList(expr_list.elements.len() as u32));
+            }
+            Expr::Map(expr_map) => {
+                for (key, value) in expr_map.entries.iter() {
+                    self.expression(key);
+                    self.expression(value);
+                }
+                self.at(&expr_map.end_brace);
+                self.emit(OpCode::BuildMap(expr_map.entries.len() as u32));
+            }
+            Expr::Index(expr_index) => {
+                self.expression(&expr_index.object);
+                self.expression(&expr_index.index);
+                self.at(&expr_index.end_bracket);
+                self.emit(OpCode::GetIndex);
+            }
+            Expr::IndexSet(expr_index_set) => {
+                self.expression(&expr_index_set.object);
+                self.expression(&expr_index_set.index);
+                self.expression(&expr_index_set.value);
+                self.at(&expr_index_set.end_bracket);
+                self.emit(OpCode::SetIndex);
+            }
+            Expr::This(expr_this) => {
+                let scope_slot = self.scope_record.get(expr_this.id);
+                let access = self.access(scope_slot, &expr_this.keyword);
+                self.at(&expr_this.keyword);
+                self.get_variable(access);
+            }
+            Expr::Super(expr_super) => {
+                // The scope of `this` is always the one just inside the scope of `super`.
+                let scope_slot = self.scope_record.get(expr_super.id);
+                let this_slot = scope_slot.map(|scope_slot| ScopeSlot {
+                    depth: scope_slot.depth - 1,
+                    slot: 0,
+                });
+                let this = self.access(this_slot, &expr_super.keyword);
+                let superclass = self.access(scope_slot, &expr_super.keyword);
+                self.at(&expr_super.keyword);
+                self.get_variable(this);
+                self.get_variable(superclass);
+
+                let name = self.chunk().add_name(&expr_super.method);
+                self.emit(OpCode::GetSuper(name));
+            }
+        }
+    }
+
+    fn binary_expr(&mut self, expr: &BinaryExpression) {
+        self.expression(&expr.left);
+        self.expression(&expr.right);
+
+        self.at(&expr.operator);
+        let op = match expr.operator.token_type {
+            TokensType::Plus => OpCode::Add,
+            TokensType::Minus => OpCode::Subtract,
+            TokensType::Star => OpCode::Multiply,
+            TokensType::Slash => OpCode::Divide,
+            TokensType::Percent => OpCode::Modulo,
+            TokensType::SlashSlash => OpCode::FloorDivide,
+            TokensType::StarStar => OpCode::Power,
+            TokensType::Greater => OpCode::Greater,
+            TokensType::GreaterEqual => OpCode::GreaterEqual,
+            TokensType::Less => OpCode::Less,
+            TokensType::LessEqual => OpCode::LessEqual,
+            TokensType::BangEqual => OpCode::NotEqual,
+            _ => OpCode::Equal,
+        };
+        self.emit(op);
+    }
+
+    /// Mirrors the tree-walker: `or` yields `true` and `and` yields `false` when short-circuiting.
+    fn logical_expr(&mut self, expr: &LogicalExpression) {
+        let is_or = expr.operator.token_type == TokensType::Or;
+
+        self.expression(&expr.left);
+        self.at(&expr.operator);
+        let falsy_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit(OpCode::Pop);
+        if is_or {
+            self.emit(OpCode::True);
+        } else {
+            self.expression(&expr.right);
+        }
+        let end_jump = self.emit_jump(OpCode::Jump);
+
+        self.patch_jump(falsy_jump);
+        self.emit(OpCode::Pop);
+        if is_or {
+            self.expression(&expr.right);
+        } else {
+            self.emit(OpCode::False);
+        }
+        self.patch_jump(end_jump);
+    }
+
+    fn literal_expr(&mut self, expr: &LiteralExpression) {
+        match &expr.value {
+            Some(ValueType::Bool(true)) => self.emit(OpCode::True),
+            Some(ValueType::Bool(false)) => self.emit(OpCode::False),
+            Some(ValueType::Number(number_val)) => {
+                let index = self
+                    .chunk()
+                    .add_constant(EnvironmentValue::Number(*number_val));
+                self.emit(OpCode::Constant(index))
+            }
+            Some(ValueType::String(string_val)) => {
+                let index = self
+                    .chunk()
+                    .add_constant(EnvironmentValue::String(string_val.clone().into()));
+                self.emit(OpCode::Constant(index))
+            }
+            None => self.emit(OpCode::Nil),
+        };
+    }
+}
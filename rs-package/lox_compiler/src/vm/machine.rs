@@ -0,0 +1,685 @@
+use crate::environment::environment_value::{EnvironmentValue, DIVISION_BY_ZERO};
+use crate::environment::lox_list::{LoxList, NOT_INDEXABLE};
+use crate::environment::lox_map::LoxMap;
+use crate::interpreter::interpreter::DEFAULT_MAX_CALL_DEPTH;
+use crate::limits::{Budget, ExecutionLimits};
+use crate::parser::statement::Stmt;
+use crate::scanner::{
+    interner::Symbol,
+    scanner::{Error, TraceFrame},
+};
+use crate::semantic::scope_analyst::ScopeRecord;
+use crate::vm::chunk::OpCode;
+use crate::vm::compiler::Compiler;
+use crate::vm::object::*;
+
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+/// Global variables of the vm, names are bound to slots at compile time.
+#[derive(Debug, Default)]
+pub struct Globals {
+    indexes: HashMap<Symbol, usize>,
+    /// Spelling of each global for "Undefined variable" errors.
+    names: Vec<Rc<String>>,
+    values: Vec<Option<EnvironmentValue>>,
+}
+
+impl Globals {
+    pub fn index(&mut self, symbol: Symbol, name: &Rc<String>) -> usize {
+        if let Some(index) = self.indexes.get(&symbol) {
+            return *index;
+        }
+        self.names.push(name.clone());
+        self.values.push(None);
+        self.indexes.insert(symbol, self.names.len() - 1);
+        self.names.len() - 1
+    }
+}
+
+/// Code and message of a runtime error, the running frame adds the position.
+type VmError = (&'static str, String);
+
+#[derive(Debug)]
+struct CallFrame {
+    closure: Rc<VmClosure>,
+    ip: usize,
+    slots: usize,
+}
+
+impl CallFrame {
+    fn error(&self, code: &'static str, message: String) -> Error {
+        let (line, column, length) = self.closure.function.chunk.positions[self.ip - 1];
+        Error {
+            length,
+            ..Error::new(line, column, code, message)
+        }
+    }
+}
+
+/// Runs compiled chunks. Closures, classes, instances, lists and maps are held by `Rc` and
+/// there is no cycle collector, an instance reachable from its own fields is never freed.
+#[derive(Debug)]
+pub struct Vm {
+    stack: Vec<EnvironmentValue>,
+    frames: Vec<CallFrame>,
+    globals: Globals,
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    max_call_depth: usize,
+    budget: Budget,
+    log_fn: Option<fn(String) -> ()>,
+}
+
+impl Vm {
+    pub fn new(log_fn: Option<fn(String) -> ()>) -> Self {
+        Vm {
+            stack: Vec::with_capacity(256),
+            frames: Vec::new(),
+            globals: Globals::default(),
+            open_upvalues: Vec::new(),
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            budget: Budget::default(),
+            log_fn,
+        }
+    }
+
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.max_call_depth = depth;
+    }
+
+    pub fn set_limits(&mut self, limits: ExecutionLimits) {
+        self.budget = Budget::new(limits);
+    }
+
+    pub fn define_native(&mut self, symbol: Symbol, name: &str, value: EnvironmentValue) {
+        let index = self.globals.index(symbol, &Rc::new(name.to_string()));
+        self.globals.values[index] = Some(value);
+    }
+
+    /// Compiles and runs resolved statements, see `Compiler::compile` for `echo`.
+    pub fn interpret(
+        &mut self,
+        statements: &[Stmt],
+        scope_record: &ScopeRecord,
+        echo: bool,
+    ) -> Result<Option<EnvironmentValue>, Error> {
+        let function = Compiler::new(&mut self.globals, scope_record).compile(statements, echo);
+        let closure = Rc::new(VmClosure {
+            function: Rc::new(function),
+            upvalues: Vec::new(),
+        });
+
+        self.budget.start();
+        self.stack
+            .push(EnvironmentValue::VmClosure(closure.clone()));
+        let frame = CallFrame {
+            closure,
+            ip: 0,
+            slots: 0,
+        };
+
+        match self.run(frame) {
+            Ok(value) => Ok(if echo { Some(value) } else { None }),
+            Err(err) => {
+                self.stack.clear();
+                self.frames.clear();
+                self.open_upvalues.clear();
+                Err(err)
+            }
+        }
+    }
+
+    fn pop(&mut self) -> EnvironmentValue {
+        self.stack.pop().unwrap()
+    }
+
+    fn peek(&self, distance: usize) -> &EnvironmentValue {
+        &self.stack[self.stack.len() - 1 - distance]
+    }
+
+    /// Executes `frame` until the outermost frame returns, a runtime error leaves with
+    /// the backtrace of the frames that were active.
+    fn run(&mut self, mut frame: CallFrame) -> Result<EnvironmentValue, Error> {
+        self.execute(&mut frame).map_err(|mut err| {
+            if self.frames.is_empty() {
+                return err;
+            }
+            err.backtrace = std::iter::once(&frame)
+                .chain(self.frames.iter().rev())
+                .map(|frame| TraceFrame {
+                    name: frame.closure.function.name.clone(),
+                    line: frame.closure.function.chunk.positions[frame.ip - 1].0,
+                })
+                .collect();
+            err
+        })
+    }
+
+    /// The dispatch loop, the running frame is kept out of `self.frames` so it doesn't
+    /// go through the vector. The chunk of the running function is held in a local until
+    /// a call or return switches frames, instead of being reached through the closure on
+    /// every instruction.
+    fn execute(&mut self, frame: &mut CallFrame) -> Result<EnvironmentValue, Error> {
+        'frames: loop {
+            let function = frame.closure.function.clone();
+            let chunk = &function.chunk;
+            loop {
+                let op = chunk.code[frame.ip];
+                frame.ip += 1;
+
+                match op {
+                    OpCode::Constant(index) => {
+                        let value = chunk.constants[index as usize].clone();
+                        self.stack.push(value);
+                    }
+                    OpCode::Nil => self.stack.push(EnvironmentValue::None),
+                    OpCode::True => self.stack.push(EnvironmentValue::Bool(true)),
+                    OpCode::False => self.stack.push(EnvironmentValue::Bool(false)),
+                    OpCode::Pop => {
+                        self.pop();
+                    }
+                    OpCode::GetLocal(slot) => {
+                        let value = self.stack[frame.slots + slot as usize].clone();
+                        self.stack.push(value);
+                    }
+                    OpCode::SetLocal(slot) => {
+                        let value = self.peek(0).clone();
+                        self.stack[frame.slots + slot as usize] = value;
+                    }
+                    OpCode::StoreLocal(slot) => {
+                        let value = self.pop();
+                        self.stack[frame.slots + slot as usize] = value;
+                    }
+                    OpCode::GetGlobal(index) => match &self.globals.values[index as usize] {
+                        Some(value) => {
+                            let value = value.clone();
+                            self.stack.push(value);
+                        }
+                        None => {
+                            let name = &self.globals.names[index as usize];
+                            return Err(
+                                frame.error("E0300", String::from("Undefined variable at ") + name)
+                            );
+                        }
+                    },
+                    OpCode::DefineGlobal(index) => {
+                        let value = self.pop();
+                        self.globals.values[index as usize] = Some(value);
+                    }
+                    OpCode::SetGlobal(index) => {
+                        // Same as the tree-walker, assigning an undeclared global defines it.
+                        let value = self.peek(0).clone();
+                        self.globals.values[index as usize] = Some(value);
+                    }
+                    OpCode::StoreGlobal(index) => {
+                        let value = self.pop();
+                        self.globals.values[index as usize] = Some(value);
+                    }
+                    OpCode::GetUpvalue(index) => {
+                        let value = match &*frame.closure.upvalues[index as usize].borrow() {
+                            Upvalue::Open(slot) => self.stack[*slot].clone(),
+                            Upvalue::Closed(value) => value.clone(),
+                        };
+                        self.stack.push(value);
+                    }
+                    OpCode::SetUpvalue(index) => {
+                        let value = self.peek(0).clone();
+                        match &mut *frame.closure.upvalues[index as usize].borrow_mut() {
+                            Upvalue::Open(slot) => self.stack[*slot] = value,
+                            Upvalue::Closed(closed) => *closed = value,
+                        }
+                    }
+                    OpCode::StoreUpvalue(index) => {
+                        let value = self.pop();
+                        match &mut *frame.closure.upvalues[index as usize].borrow_mut() {
+                            Upvalue::Open(slot) => self.stack[*slot] = value,
+                            Upvalue::Closed(closed) => *closed = value,
+                        }
+                    }
+                    OpCode::GetProperty(index) => {
+                        let (symbol, name) = &chunk.names[index as usize];
+                        let value = self
+                            .get_property(*symbol, name)
+                            .map_err(|(code, message)| frame.error(code, message))?;
+                        self.pop();
+                        self.stack.push(value);
+                    }
+                    OpCode::SetProperty(index) => {
+                        let (symbol, name) = &chunk.names[index as usize];
+                        match self.peek(1) {
+                            EnvironmentValue::VmInstance(instance) => {
+                                let value = self.peek(0).clone();
+                                instance.borrow_mut().set(*symbol, value.clone());
+                                self.pop();
+                                self.pop();
+                                self.stack.push(value);
+                            }
+                            _ => {
+                                return Err(frame.error(
+                                    "E0305",
+                                    format!("Only instances have properties at {}", name),
+                                ))
+                            }
+                        }
+                    }
+                    OpCode::GetSuper(index) => {
+                        let (symbol, name) = &chunk.names[index as usize];
+                        let superclass = self.pop();
+                        let receiver = self.pop();
+                        let value = match superclass {
+                            EnvironmentValue::VmClass(superclass) => {
+                                Vm::bind_method(&superclass, *symbol, receiver)
+                            }
+                            _ => None,
+                        };
+                        match value {
+                            Some(value) => self.stack.push(value),
+                            None => {
+                                return Err(
+                                    frame.error("E0306", format!("Undefined property {}", name))
+                                )
+                            }
+                        }
+                    }
+                    OpCode::BuildList(count) => {
+                        let values = self.stack.split_off(self.stack.len() - count as usize);
+                        let list = LoxList::new(values);
+                        self.budget
+                            .check_memory()
+                            .map_err(|(code, message)| frame.error(code, message))?;
+                        self.stack
+                            .push(EnvironmentValue::VmList(Rc::new(RefCell::new(list))));
+                    }
+                    OpCode::BuildMap(count) => {
+                        let values = self.stack.split_off(self.stack.len() - 2 * count as usize);
+                        let mut values = values.into_iter();
+                        let mut entries = Vec::with_capacity(count as usize);
+                        while let (Some(key), Some(value)) = (values.next(), values.next()) {
+                            entries.push((key, value));
+                        }
+                        let map = LoxMap::new(entries)
+                            .map_err(|(code, message)| frame.error(code, message))?;
+                        self.budget
+                            .check_memory()
+                            .map_err(|(code, message)| frame.error(code, message))?;
+                        self.stack
+                            .push(EnvironmentValue::VmMap(Rc::new(RefCell::new(map))));
+                    }
+                    OpCode::GetIndex => {
+                        let index = self.pop();
+                        let value = match self.pop() {
+                            EnvironmentValue::VmList(list) => list.borrow().get(&index),
+                            EnvironmentValue::VmMap(map) => map.borrow().get(&index),
+                            _ => Err(Vm::not_indexable()),
+                        };
+                        let value = value.map_err(|(code, message)| frame.error(code, message))?;
+                        self.stack.push(value);
+                    }
+                    OpCode::SetIndex => {
+                        let value = self.pop();
+                        let index = self.pop();
+                        let result = match self.pop() {
+                            EnvironmentValue::VmList(list) => {
+                                list.borrow_mut().set(&index, value.clone())
+                            }
+                            EnvironmentValue::VmMap(map) => map
+                                .borrow_mut()
+                                .set(&index, value.clone())
+                                .and_then(|_| self.budget.check_memory()),
+                            _ => Err(Vm::not_indexable()),
+                        };
+                        result.map_err(|(code, message)| frame.error(code, message))?;
+                        self.stack.push(value);
+                    }
+                    OpCode::Equal => {
+                        let right = self.pop();
+                        let left = self.pop();
+                        match EnvironmentValue::eq(&left, &right) {
+                            Some(value) => self.stack.push(value),
+                            None => return Err(frame.error("E0302", Vm::equality_message("=="))),
+                        }
+                    }
+                    OpCode::NotEqual => {
+                        let right = self.pop();
+                        let left = self.pop();
+                        match EnvironmentValue::partial_eq(&left, &right) {
+                            Some(value) => self.stack.push(value),
+                            None => return Err(frame.error("E0302", Vm::equality_message("!="))),
+                        }
+                    }
+                    OpCode::Greater => self.binary(frame, EnvironmentValue::gt, ">")?,
+                    OpCode::GreaterEqual => self.binary(frame, EnvironmentValue::ge, ">=")?,
+                    OpCode::Less => self.binary(frame, EnvironmentValue::lt, "<")?,
+                    OpCode::LessEqual => self.binary(frame, EnvironmentValue::le, "<=")?,
+                    OpCode::Add => {
+                        self.binary(frame, EnvironmentValue::add, "+")?;
+                        self.budget
+                            .check_memory()
+                            .map_err(|(code, message)| frame.error(code, message))?;
+                    }
+                    OpCode::Subtract => self.binary(frame, EnvironmentValue::sub, "-")?,
+                    OpCode::Multiply => self.binary(frame, EnvironmentValue::mul, "*")?,
+                    OpCode::Divide => self.binary(frame, EnvironmentValue::div, "/")?,
+                    OpCode::Modulo => self.division(frame, EnvironmentValue::rem, "%")?,
+                    OpCode::FloorDivide => {
+                        self.division(frame, EnvironmentValue::floor_div, "//")?
+                    }
+                    OpCode::Power => self.binary(frame, EnvironmentValue::pow, "**")?,
+                    OpCode::Not => {
+                        let value = self.pop();
+                        self.stack.push(EnvironmentValue::Bool(!value.is_truthy()));
+                    }
+                    OpCode::Negate => match EnvironmentValue::neg(self.peek(0)) {
+                        Some(value) => {
+                            self.pop();
+                            self.stack.push(value);
+                        }
+                        None => {
+                            return Err(
+                                frame.error("E0309", String::from("Operand must be a number at -"))
+                            )
+                        }
+                    },
+                    OpCode::Print => {
+                        let value = self.pop();
+                        if let Some(log_fn) = self.log_fn {
+                            log_fn(value.display(None));
+                        } else {
+                            println!("{}", value.display(None));
+                        }
+                    }
+                    OpCode::Jump(target) => frame.ip = target as usize,
+                    OpCode::Loop(target) => {
+                        self.budget
+                            .step()
+                            .map_err(|(code, message)| frame.error(code, message))?;
+                        frame.ip = target as usize;
+                    }
+                    OpCode::JumpIfFalse(target) => {
+                        if !self.peek(0).is_truthy() {
+                            frame.ip = target as usize;
+                        }
+                    }
+                    OpCode::PopJumpIfFalse(target) => {
+                        if !self.pop().is_truthy() {
+                            frame.ip = target as usize;
+                        }
+                    }
+                    OpCode::Call(arg_count) => {
+                        let callee = self.peek(arg_count as usize).clone();
+                        let callee_frame = self
+                            .call_value(callee, arg_count as usize)
+                            .map_err(|(code, message)| frame.error(code, message))?;
+
+                        if let Some(callee_frame) = callee_frame {
+                            self.frames.push(std::mem::replace(frame, callee_frame));
+                            continue 'frames;
+                        }
+                    }
+                    OpCode::Closure(index) => {
+                        let function =
+                            frame.closure.function.chunk.functions[index as usize].clone();
+                        let upvalues = function
+                            .upvalues
+                            .iter()
+                            .map(|descriptor| {
+                                if descriptor.is_local {
+                                    self.capture_upvalue(frame.slots + descriptor.index as usize)
+                                } else {
+                                    frame.closure.upvalues[descriptor.index as usize].clone()
+                                }
+                            })
+                            .collect::<Vec<_>>();
+
+                        self.stack
+                            .push(EnvironmentValue::VmClosure(Rc::new(VmClosure {
+                                function,
+                                upvalues,
+                            })));
+                    }
+                    OpCode::CloseUpvalue => {
+                        self.close_upvalues(self.stack.len() - 1);
+                        self.pop();
+                    }
+                    OpCode::Return => {
+                        let result = self.pop();
+                        self.close_upvalues(frame.slots);
+                        self.stack.truncate(frame.slots);
+
+                        match self.frames.pop() {
+                            Some(caller) => *frame = caller,
+                            None => return Ok(result),
+                        }
+                        self.stack.push(result);
+                        continue 'frames;
+                    }
+                    OpCode::Class(index) => {
+                        let (_, name) = &chunk.names[index as usize];
+                        self.stack
+                            .push(EnvironmentValue::VmClass(Rc::new(RefCell::new(VmClass {
+                                name: name.clone(),
+                                methods: HashMap::new(),
+                            }))));
+                    }
+                    OpCode::Inherit => {
+                        let subclass = match self.peek(0) {
+                            EnvironmentValue::VmClass(subclass) => subclass.clone(),
+                            _ => unreachable!(),
+                        };
+                        match self.peek(1) {
+                            EnvironmentValue::VmClass(superclass) => {
+                                let methods = superclass.borrow().methods.clone();
+                                subclass.borrow_mut().methods.extend(methods);
+                            }
+                            _ => {
+                                let name = subclass.borrow().name.clone();
+                                return Err(frame.error(
+                                    "E0307",
+                                    format!("Superclass must be a class at {}", name),
+                                ));
+                            }
+                        }
+                        self.pop();
+                    }
+                    OpCode::Method(index) => {
+                        let symbol = chunk.names[index as usize].0;
+                        let method = match self.pop() {
+                            EnvironmentValue::VmClosure(method) => method,
+                            _ => unreachable!(),
+                        };
+                        if let EnvironmentValue::VmClass(class) = self.peek(0) {
+                            class.borrow_mut().methods.insert(symbol, method);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn binary(
+        &mut self,
+        frame: &CallFrame,
+        calculate: fn(&EnvironmentValue, &EnvironmentValue) -> Option<EnvironmentValue>,
+        lexeme: &str,
+    ) -> Result<(), Error> {
+        // The result replaces the left operand in place rather than popping both operands.
+        let right = self.pop();
+        let left = self.stack.last_mut().unwrap();
+        match calculate(left, &right) {
+            Some(value) => {
+                *left = value;
+                Ok(())
+            }
+            None => Err(frame.error(
+                "E0301",
+                format!("Operands must be two numbers or two strings {}", lexeme),
+            )),
+        }
+    }
+
+    /// A binary operator that fails on a zero right operand.
+    fn division(
+        &mut self,
+        frame: &CallFrame,
+        calculate: fn(&EnvironmentValue, &EnvironmentValue) -> Option<EnvironmentValue>,
+        lexeme: &str,
+    ) -> Result<(), Error> {
+        if self.peek(1).is_number() && self.peek(0).is_zero() {
+            let message = format!("Division by zero at {}", lexeme);
+            return Err(frame.error(DIVISION_BY_ZERO, message));
+        }
+        self.binary(frame, calculate, lexeme)
+    }
+
+    fn equality_message(lexeme: &str) -> String {
+        format!(
+            r#""!=" and "==" operands only support number/string/boolean {}"#,
+            lexeme
+        )
+    }
+
+    fn arity_error(arity: usize, arg_count: usize) -> VmError {
+        let message = format!(
+            r#"Expect {} arguments but got {}, at ")""#,
+            arity, arg_count
+        );
+        ("E0303", message)
+    }
+
+    fn not_indexable() -> VmError {
+        (
+            NOT_INDEXABLE,
+            String::from("Only lists and maps can be indexed"),
+        )
+    }
+
+    fn get_property(&self, symbol: Symbol, name: &str) -> Result<EnvironmentValue, VmError> {
+        let instance = match self.peek(0) {
+            EnvironmentValue::VmInstance(instance) => instance,
+            list @ EnvironmentValue::VmList(_) => {
+                return LoxList::method(list, name)
+                    .ok_or_else(|| ("E0306", format!("Undefined property {}", name)))
+            }
+            map @ EnvironmentValue::VmMap(_) => {
+                return LoxMap::method(map, name)
+                    .ok_or_else(|| ("E0306", format!("Undefined property {}", name)))
+            }
+            _ => {
+                let message = format!("Only instances have properties at {}", name);
+                return Err(("E0305", message));
+            }
+        };
+
+        if let Some(value) = instance.borrow().fields.get(&symbol) {
+            return Ok(value.clone());
+        }
+        Vm::bind_method(&instance.borrow().class, symbol, self.peek(0).clone())
+            .ok_or_else(|| ("E0306", format!("Undefined property {}", name)))
+    }
+
+    fn bind_method(
+        class: &Rc<RefCell<VmClass>>,
+        name: Symbol,
+        receiver: EnvironmentValue,
+    ) -> Option<EnvironmentValue> {
+        class.borrow().methods.get(&name).map(|method| {
+            EnvironmentValue::VmBoundMethod(Rc::new(VmBoundMethod {
+                receiver,
+                method: method.clone(),
+            }))
+        })
+    }
+
+    /// Calls `callee`, returning the frame to switch to when it is a Lox function.
+    fn call_value(
+        &mut self,
+        callee: EnvironmentValue,
+        arg_count: usize,
+    ) -> Result<Option<CallFrame>, VmError> {
+        let callee_slot = self.stack.len() - arg_count - 1;
+
+        match callee {
+            EnvironmentValue::VmClosure(closure) => self.call(closure, arg_count).map(Some),
+            EnvironmentValue::VmBoundMethod(bound) => {
+                self.stack[callee_slot] = bound.receiver.clone();
+                self.call(bound.method.clone(), arg_count).map(Some)
+            }
+            EnvironmentValue::VmClass(class) => {
+                let instance = VmInstance::new(class.clone());
+                self.stack[callee_slot] =
+                    EnvironmentValue::VmInstance(Rc::new(RefCell::new(instance)));
+
+                let initializer = class.borrow().methods.get(&Symbol::INIT).cloned();
+                match initializer {
+                    Some(initializer) => self.call(initializer, arg_count).map(Some),
+                    None if arg_count != 0 => Err(Vm::arity_error(0, arg_count)),
+                    None => Ok(None),
+                }
+            }
+            EnvironmentValue::LoxNativeFunction(native) => {
+                if arg_count != native.arity() {
+                    return Err(Vm::arity_error(native.arity(), arg_count));
+                }
+                let value =
+                    native
+                        .call(None, &self.stack[callee_slot + 1..])
+                        .map_err(|message| {
+                            let message =
+                                format!("{} in native function {}", message, native.name());
+                            ("E0308", message)
+                        })?;
+                self.stack.truncate(callee_slot);
+                self.stack.push(value);
+                Ok(None)
+            }
+            _ => Err((
+                "E0304",
+                String::from("Can only call functions and classes at )"),
+            )),
+        }
+    }
+
+    fn call(&mut self, closure: Rc<VmClosure>, arg_count: usize) -> Result<CallFrame, VmError> {
+        if arg_count != closure.function.arity {
+            return Err(Vm::arity_error(closure.function.arity, arg_count));
+        }
+        if self.frames.len() >= self.max_call_depth {
+            return Err(("E0310", String::from("Stack overflow")));
+        }
+        self.budget.step()?;
+        Ok(CallFrame {
+            closure,
+            ip: 0,
+            slots: self.stack.len() - arg_count - 1,
+        })
+    }
+
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        for upvalue in self.open_upvalues.iter() {
+            if let Upvalue::Open(open_slot) = &*upvalue.borrow() {
+                if *open_slot == slot {
+                    return upvalue.clone();
+                }
+            }
+        }
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        self.open_upvalues.push(upvalue.clone());
+        upvalue
+    }
+
+    /// Moves every captured slot at or above `from` off the stack into its upvalue.
+    fn close_upvalues(&mut self, from: usize) {
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let slot = match &*upvalue.borrow() {
+                Upvalue::Open(slot) => *slot,
+                Upvalue::Closed(_) => return false,
+            };
+            if slot < from {
+                return true;
+            }
+            *upvalue.borrow_mut() = Upvalue::Closed(stack[slot].clone());
+            false
+        });
+    }
+}
@@ -0,0 +1,79 @@
+use crate::environment::environment_value::EnvironmentValue;
+use crate::memory::Charge;
+use crate::scanner::interner::Symbol;
+use crate::vm::chunk::Chunk;
+
+use std::{cell::RefCell, collections::HashMap, mem, rc::Rc};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct UpvalueDescriptor {
+    pub is_local: bool,
+    pub index: u32,
+}
+
+/// A compiled function body, shared by every closure created from it.
+#[derive(Debug)]
+pub struct VmFunction {
+    pub name: Rc<String>,
+    pub arity: usize,
+    pub kind: FunctionKind,
+    pub chunk: Chunk,
+    pub upvalues: Vec<UpvalueDescriptor>,
+}
+
+/// A captured variable, `Open` points at a live stack slot until its scope ends.
+#[derive(Debug)]
+pub enum Upvalue {
+    Open(usize),
+    Closed(EnvironmentValue),
+}
+
+#[derive(Debug)]
+pub struct VmClosure {
+    pub function: Rc<VmFunction>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+#[derive(Debug)]
+pub struct VmClass {
+    pub name: Rc<String>,
+    pub methods: HashMap<Symbol, Rc<VmClosure>>,
+}
+
+#[derive(Debug)]
+pub struct VmInstance {
+    pub class: Rc<RefCell<VmClass>>,
+    pub fields: HashMap<Symbol, EnvironmentValue>,
+    charge: Charge,
+}
+
+impl VmInstance {
+    pub fn new(class: Rc<RefCell<VmClass>>) -> Self {
+        VmInstance {
+            class,
+            fields: HashMap::new(),
+            charge: Charge::new(mem::size_of::<VmInstance>()),
+        }
+    }
+
+    pub fn set(&mut self, name: Symbol, value: EnvironmentValue) {
+        if self.fields.insert(name, value).is_none() {
+            self.charge
+                .grow(mem::size_of::<(Symbol, EnvironmentValue)>());
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct VmBoundMethod {
+    pub receiver: EnvironmentValue,
+    pub method: Rc<VmClosure>,
+}
@@ -0,0 +1,241 @@
+//! Scripts stopped by the interpreter's limits rather than by a bug in them.
+
+use lox_compiler::{interpret_with, Backend, ExecutionLimits, Interpreter, LoxError};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::thread;
+
+/// The main thread of the `lox` binary, which the default call depth is sized for.
+const MAIN_THREAD_STACK: usize = 8 * 1024 * 1024;
+
+/// Runs `f` on a thread with the stack of a main thread rather than the smaller one of a
+/// test thread.
+fn on_main_thread_stack<F>(f: F)
+where
+    F: FnOnce() + Send + 'static,
+{
+    thread::Builder::new()
+        .stack_size(MAIN_THREAD_STACK)
+        .spawn(f)
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+fn run(code: &str, backend: Backend) -> Result<(), LoxError> {
+    run_limited(code, backend, ExecutionLimits::default())
+}
+
+fn run_limited(code: &str, backend: Backend, limits: ExecutionLimits) -> Result<(), LoxError> {
+    let mut interpreter = Interpreter::with_backend(None, backend);
+    interpreter.set_limits(limits);
+    interpret_with(code, &mut interpreter)
+}
+
+/// The code and message of the only error of a script stopped by its limits.
+fn limit_exceeded(result: Result<(), LoxError>) -> (&'static str, String) {
+    match result {
+        Err(LoxError::LimitExceeded(errors)) => (errors[0].code, errors[0].message.clone()),
+        other => panic!("expected an exceeded limit, got {:?}", other),
+    }
+}
+
+fn stack_overflow_at_default_depth(backend: Backend) {
+    on_main_thread_stack(move || {
+        let err = run("fun f(n) { return f(n + 1); }\nf(0);", backend).unwrap_err();
+
+        let error = &err.errors()[0];
+        assert_eq!(
+            (error.code, error.message.as_str()),
+            ("E0310", "Stack overflow")
+        );
+        // Every call to `f` that was let through, and the script calling the first one.
+        assert_eq!(error.backtrace.len(), 257);
+        assert!(error.backtrace[..256]
+            .iter()
+            .all(|frame| frame.to_string() == "at f (line 1)"));
+        assert_eq!(error.backtrace[256].to_string(), "at script (line 2)");
+    });
+}
+
+#[test]
+fn stack_overflow_at_default_depth_tree_walker() {
+    stack_overflow_at_default_depth(Backend::TreeWalker);
+}
+
+#[test]
+fn stack_overflow_at_default_depth_vm() {
+    stack_overflow_at_default_depth(Backend::Vm);
+}
+
+/// `fun r(n) { if (true) { if (true) { ... return r(n + 1); } } }`, each call nests
+/// `depth` blocks deep.
+fn nested_blocks_in_each_call(depth: usize) -> String {
+    format!(
+        "fun r(n) {{ {} return r(n + 1); {} }}\nr(0);",
+        "if (true) { ".repeat(depth),
+        "} ".repeat(depth)
+    )
+}
+
+/// `fun r(n) { return (1 + (1 + ... r(n + 1))); }`, each call nests `depth` groupings deep.
+fn nested_expressions_in_each_call(depth: usize) -> String {
+    format!(
+        "fun r(n) {{ return {}r(n + 1){}; }}\nr(0);",
+        "(1 + ".repeat(depth),
+        ")".repeat(depth)
+    )
+}
+
+fn stack_overflow_in_nested_code(backend: Backend) {
+    on_main_thread_stack(move || {
+        for code in [
+            nested_blocks_in_each_call(40),
+            nested_expressions_in_each_call(60),
+        ] {
+            let err = run(&code, backend).unwrap_err();
+
+            let error = &err.errors()[0];
+            assert_eq!(
+                (error.code, error.message.as_str()),
+                ("E0310", "Stack overflow")
+            );
+            assert_eq!(
+                error.backtrace.last().unwrap().to_string(),
+                "at script (line 2)"
+            );
+        }
+    });
+}
+
+#[test]
+fn stack_overflow_in_nested_code_tree_walker() {
+    stack_overflow_in_nested_code(Backend::TreeWalker);
+}
+
+#[test]
+fn stack_overflow_in_nested_code_vm() {
+    stack_overflow_in_nested_code(Backend::Vm);
+}
+
+/// Less stack than the call depth needs, the tree-walker stops once it is used up.
+#[test]
+fn stack_overflow_past_max_stack() {
+    let mut interpreter = Interpreter::new(None);
+    interpreter.set_max_stack(64 * 1024);
+    let err = interpret_with("fun f(n) { return f(n + 1); }\nf(0);", &mut interpreter).unwrap_err();
+
+    let error = &err.errors()[0];
+    assert_eq!((error.code, error.line), ("E0310", 1));
+    assert!(error.backtrace.len() < 257, "{}", error.backtrace.len());
+}
+
+fn step_budget(backend: Backend) {
+    let limits = ExecutionLimits {
+        max_steps: Some(100),
+        ..Default::default()
+    };
+    let result = run_limited("var i = 0; while (true) { i = i + 1; }", backend, limits);
+
+    assert_eq!(
+        limit_exceeded(result),
+        (
+            "E0311",
+            String::from("Execution limit exceeded, the script took more than 100 steps")
+        )
+    );
+}
+
+#[test]
+fn step_budget_tree_walker() {
+    step_budget(Backend::TreeWalker);
+}
+
+#[test]
+fn step_budget_vm() {
+    step_budget(Backend::Vm);
+}
+
+fn cancelled_before_the_run(backend: Backend) {
+    let cancel = Arc::new(AtomicBool::new(false));
+    let limits = ExecutionLimits {
+        cancel: Some(cancel.clone()),
+        ..Default::default()
+    };
+    cancel.store(true, Ordering::Relaxed);
+    let result = run_limited("for (;;) {}", backend, limits);
+
+    assert_eq!(
+        limit_exceeded(result),
+        (
+            "E0311",
+            String::from("Execution limit exceeded, the script was cancelled")
+        )
+    );
+}
+
+#[test]
+fn cancelled_before_the_run_tree_walker() {
+    cancelled_before_the_run(Backend::TreeWalker);
+}
+
+#[test]
+fn cancelled_before_the_run_vm() {
+    cancelled_before_the_run(Backend::Vm);
+}
+
+/// Instances pointing at themselves, which the vm never frees.
+const LEAK: &str = "class A {} for (var i = 0; i < 1000; i = i + 1) { var a = A(); a.self = a; }";
+
+#[test]
+fn memory_leaked_by_an_earlier_run_is_not_charged_to_the_next() {
+    for _ in 0..20 {
+        run(LEAK, Backend::Vm).unwrap();
+    }
+
+    for backend in [Backend::TreeWalker, Backend::Vm] {
+        let limits = ExecutionLimits {
+            max_memory: Some(200_000),
+            ..Default::default()
+        };
+        let code = "var sum = 0; for (var i = 0; i < 100; i = i + 1) { sum = sum + i; }";
+        run_limited(code, backend, limits).unwrap();
+    }
+}
+
+/// The vm has no cycle collector, values referring to themselves are never freed and
+/// count against the memory limit until it is exceeded. The tree-walker's heap frees them.
+fn only_the_vm_leaks(code: &str) {
+    let limits = ExecutionLimits {
+        max_memory: Some(2_000_000),
+        ..Default::default()
+    };
+
+    run_limited(code, Backend::TreeWalker, limits.clone()).unwrap();
+
+    let err = run_limited(code, Backend::Vm, limits).unwrap_err();
+    assert_eq!(err.errors()[0].code, "E0312");
+}
+
+#[test]
+fn only_the_vm_leaks_cycles() {
+    only_the_vm_leaks(
+        "class A {} for (var i = 0; i < 50000; i = i + 1) { var a = A(); a.self = a; }",
+    );
+}
+
+#[test]
+fn only_the_vm_leaks_lists_containing_themselves() {
+    only_the_vm_leaks(
+        "for (var i = 0; i < 200000; i = i + 1) { var xs = [1, 2, 3]; xs.push(xs); }",
+    );
+}
+
+#[test]
+fn only_the_vm_leaks_maps_containing_themselves() {
+    only_the_vm_leaks(
+        "for (var i = 0; i < 100000; i = i + 1) { var m = {\"a\": 1}; m[\"self\"] = m; }",
+    );
+}
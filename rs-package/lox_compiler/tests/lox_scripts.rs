@@ -0,0 +1,267 @@
+//! Runs the `.lox` scripts under `tests/<suite>/` on both backends, each script states
+//! the lines it prints with `// expect: <line>` comments, the code of the error it stops
+//! with, if any, with `// expect error: <code>`, and the frames of that error's backtrace
+//! with `// expect backtrace: at <function> (line <n>)`. Scripts under `tests/hash_comments/`
+//! are scanned with `#` comments and write `# expect` instead.
+
+use lox_compiler::{interpret_with, Backend, CommentSyntax, Interpreter};
+use std::{
+    cell::RefCell,
+    fs,
+    path::{Path, PathBuf},
+};
+
+thread_local! {
+    static OUTPUT: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+fn log(line: String) {
+    OUTPUT.with(|output| output.borrow_mut().push(line));
+}
+
+fn expected_lines(source: &str, prefix: &str) -> Vec<String> {
+    source
+        .lines()
+        .filter_map(|line| line.split_once(prefix))
+        .map(|(_, expected)| expected.to_string())
+        .collect()
+}
+
+fn expected_error<'a>(source: &'a str, comment: &str) -> Option<&'a str> {
+    let prefix = format!("{} expect error: ", comment);
+    source
+        .lines()
+        .find_map(|line| line.split_once(prefix.as_str()))
+        .map(|(_, code)| code.trim())
+}
+
+fn comments(suite: &Path) -> CommentSyntax {
+    if suite.ends_with("hash_comments") {
+        CommentSyntax::Hash
+    } else {
+        CommentSyntax::DoubleSlash
+    }
+}
+
+fn comment_prefix(comments: CommentSyntax) -> &'static str {
+    match comments {
+        CommentSyntax::DoubleSlash => "//",
+        CommentSyntax::Hash => "#",
+    }
+}
+
+fn tests_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests")
+}
+
+fn scripts(dir: &Path) -> Vec<PathBuf> {
+    let mut scripts = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "lox"))
+        .collect::<Vec<_>>();
+    scripts.sort();
+    assert!(!scripts.is_empty(), "no scripts in {}", dir.display());
+    scripts
+}
+
+/// What a script printed, the code of the error it stopped with and the backtrace of it.
+#[derive(Debug, PartialEq)]
+struct Run {
+    output: Vec<String>,
+    error: Option<&'static str>,
+    backtrace: Vec<String>,
+}
+
+fn run_script(source: &str, backend: Backend, comments: CommentSyntax) -> Run {
+    OUTPUT.with(|output| output.borrow_mut().clear());
+    let mut interpreter = Interpreter::with_backend(Some(log), backend);
+    interpreter.comments = comments;
+    let result = interpret_with(source, &mut interpreter);
+    let output = OUTPUT.with(|output| output.take());
+    let error = result.err().map(|err| err.errors()[0].clone());
+    Run {
+        output,
+        error: error.as_ref().map(|error| error.code),
+        backtrace: error
+            .map(|error| {
+                error
+                    .backtrace
+                    .iter()
+                    .map(|frame| frame.to_string())
+                    .collect()
+            })
+            .unwrap_or_default(),
+    }
+}
+
+fn run_suite(suite: &str, backend: Backend) {
+    let suite = tests_dir().join(suite);
+    let comments = comments(&suite);
+    let comment = comment_prefix(comments);
+    let mut failures = Vec::new();
+    for script in scripts(&suite).iter() {
+        let source = fs::read_to_string(script).unwrap();
+        let run = run_script(&source, backend, comments);
+        let expected = expected_lines(&source, &format!("{} expect: ", comment));
+        let expected_backtrace =
+            expected_lines(&source, &format!("{} expect backtrace: ", comment));
+        let expected_error = expected_error(&source, comment);
+
+        if run.error != expected_error {
+            failures.push(format!(
+                "{}: expected error {:?}, got {:?}",
+                script.display(),
+                expected_error,
+                run.error
+            ));
+        } else if run.output != expected {
+            failures.push(format!(
+                "{}: expected {:?}, printed {:?}",
+                script.display(),
+                expected,
+                run.output
+            ));
+        } else if run.backtrace != expected_backtrace {
+            failures.push(format!(
+                "{}: expected backtrace {:?}, got {:?}",
+                script.display(),
+                expected_backtrace,
+                run.backtrace
+            ));
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn closures_tree_walker() {
+    run_suite("closures", Backend::TreeWalker);
+}
+
+#[test]
+fn closures_vm() {
+    run_suite("closures", Backend::Vm);
+}
+
+#[test]
+fn classes_tree_walker() {
+    run_suite("classes", Backend::TreeWalker);
+}
+
+#[test]
+fn classes_vm() {
+    run_suite("classes", Backend::Vm);
+}
+
+#[test]
+fn lists_tree_walker() {
+    run_suite("lists", Backend::TreeWalker);
+}
+
+#[test]
+fn lists_vm() {
+    run_suite("lists", Backend::Vm);
+}
+
+#[test]
+fn maps_tree_walker() {
+    run_suite("maps", Backend::TreeWalker);
+}
+
+#[test]
+fn maps_vm() {
+    run_suite("maps", Backend::Vm);
+}
+
+#[test]
+fn loops_tree_walker() {
+    run_suite("loops", Backend::TreeWalker);
+}
+
+#[test]
+fn loops_vm() {
+    run_suite("loops", Backend::Vm);
+}
+
+#[test]
+fn for_tree_walker() {
+    run_suite("for", Backend::TreeWalker);
+}
+
+#[test]
+fn for_vm() {
+    run_suite("for", Backend::Vm);
+}
+
+#[test]
+fn arithmetic_tree_walker() {
+    run_suite("arithmetic", Backend::TreeWalker);
+}
+
+#[test]
+fn arithmetic_vm() {
+    run_suite("arithmetic", Backend::Vm);
+}
+
+#[test]
+fn hash_comments_tree_walker() {
+    run_suite("hash_comments", Backend::TreeWalker);
+}
+
+#[test]
+fn hash_comments_vm() {
+    run_suite("hash_comments", Backend::Vm);
+}
+
+#[test]
+fn backtrace_tree_walker() {
+    run_suite("backtrace", Backend::TreeWalker);
+}
+
+#[test]
+fn backtrace_vm() {
+    run_suite("backtrace", Backend::Vm);
+}
+
+#[test]
+fn gc_tree_walker() {
+    run_suite("gc", Backend::TreeWalker);
+}
+
+#[test]
+fn gc_vm() {
+    run_suite("gc", Backend::Vm);
+}
+
+/// Every script of every suite prints the same lines and stops with the same error on both
+/// backends, whether or not its `expect` comments cover all of it.
+#[test]
+fn backends_agree() {
+    let mut suites = fs::read_dir(tests_dir())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_dir())
+        .collect::<Vec<_>>();
+    suites.sort();
+
+    let mut failures = Vec::new();
+    for suite in suites.iter() {
+        for script in scripts(suite).iter() {
+            let source = fs::read_to_string(script).unwrap();
+            let tree_walker = run_script(&source, Backend::TreeWalker, comments(suite));
+            let vm = run_script(&source, Backend::Vm, comments(suite));
+            if tree_walker != vm {
+                failures.push(format!(
+                    "{}: the tree-walker gave {:?}, the vm {:?}",
+                    script.display(),
+                    tree_walker,
+                    vm
+                ));
+            }
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
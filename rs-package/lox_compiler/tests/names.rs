@@ -0,0 +1,50 @@
+//! Globals, fields and methods are looked up by symbol, so names declared by one source or by
+//! the host are found by later sources run on the same interpreter.
+
+use lox_compiler::{interpret_with, Backend, EnvironmentValue, Interpreter};
+use std::cell::RefCell;
+
+thread_local! {
+    static OUTPUT: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+fn log(line: String) {
+    OUTPUT.with(|output| output.borrow_mut().push(line));
+}
+
+fn run_sources(backend: Backend, sources: &[&str]) -> Vec<String> {
+    OUTPUT.with(|output| output.borrow_mut().clear());
+    let mut interpreter = Interpreter::with_backend(Some(log), backend);
+    interpreter.define_native("twice", 1, |args| match &args[0] {
+        EnvironmentValue::Number(number) => Ok(EnvironmentValue::Number(number * 2_f64)),
+        _ => Err(String::from("Expect a number")),
+    });
+    for source in sources.iter() {
+        interpret_with(source, &mut interpreter).unwrap();
+    }
+    OUTPUT.with(|output| output.take())
+}
+
+fn names_across_sources(backend: Backend) {
+    let output = run_sources(
+        backend,
+        &[
+            "class Point { init(x) { this.x = x; } scaled() { return Point(twice(this.x)); } }",
+            "var p = Point(2).scaled();",
+            "p.label = \"p\"; fun show(point) { print point.label; print point.x; }",
+            "show(p); print Point(3).scaled().x;",
+        ],
+    );
+
+    assert_eq!(output, vec!["p", "4", "6"]);
+}
+
+#[test]
+fn names_across_sources_tree_walker() {
+    names_across_sources(Backend::TreeWalker);
+}
+
+#[test]
+fn names_across_sources_vm() {
+    names_across_sources(Backend::Vm);
+}
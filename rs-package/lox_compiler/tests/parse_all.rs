@@ -0,0 +1,58 @@
+//! `parse_all` keeps parsing after a syntax error, reporting every error together with the
+//! statements it could still parse.
+
+use lox_compiler::{parse_all, parser::statement::Stmt};
+
+#[test]
+fn reports_every_syntax_error_and_keeps_later_statements() {
+    let (statements, errors) = parse_all(
+        "var a = 1;
+var = 2;
+print a;
+print (;
+var b = 3;",
+    )
+    .unwrap();
+
+    assert_eq!(errors.len(), 2, "{:?}", errors);
+    assert_eq!(errors[0].line, 2);
+    assert_eq!(errors[1].line, 4);
+
+    let names = statements
+        .iter()
+        .map(|stmt| match stmt {
+            Stmt::Var(stmt) => format!("var {}", stmt.name.lexeme),
+            Stmt::Print(_) => String::from("print"),
+            _ => String::from("other"),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["var a", "print", "var b"]);
+}
+
+#[test]
+fn recovers_at_break_and_continue() {
+    let (statements, errors) = parse_all(
+        "while (true) {
+  var = 1
+  continue;
+  var = 2
+  break;
+}",
+    )
+    .unwrap();
+
+    assert_eq!(errors.len(), 2, "{:?}", errors);
+
+    let body = match &statements[..] {
+        [Stmt::While(stmt)] => match &stmt.body {
+            Stmt::Block(block) => &block.statements,
+            other => panic!("expected a block, got {:?}", other),
+        },
+        other => panic!("expected a while loop, got {:?}", other),
+    };
+    assert!(
+        matches!(body[..], [Stmt::Continue(_), Stmt::Break(_)]),
+        "{:?}",
+        body
+    );
+}
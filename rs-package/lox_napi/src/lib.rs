@@ -1,19 +1,142 @@
 #[macro_use]
 extern crate napi_derive;
 
+use lox_compiler::ExecutionLimits;
 use napi::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
 #[module_exports]
 fn init(mut exports: JsObject) -> Result<()> {
     exports.create_named_method("interpret_lox", interpret_lox)?;
+    exports.create_named_method("interpret_lox_async", interpret_lox_async)?;
 
     Ok(())
 }
 
-#[js_function(1)]
+/// `interpret_lox(code, timeoutMs?, maxSteps?, maxMemory?)`, a script past any of the
+/// limits throws an "Execution limit exceeded" or "Memory limit exceeded" error.
+#[js_function(4)]
 fn interpret_lox(ctx: CallContext) -> Result<JsUndefined> {
     let code = ctx.get::<JsString>(0)?.into_utf8()?;
     let code = code.as_str()?.to_string();
-    lox_compiler::interpret(&code, None);
+
+    interpret(&code, limits(&ctx)?)?;
     ctx.env.get_undefined()
 }
+
+/// `interpret_lox_async(code, timeoutMs?, maxSteps?, maxMemory?, signal?)` runs the script
+/// on the libuv thread pool and returns a promise. Aborting the `AbortSignal` cancels the
+/// script, the promise then rejects with an "Execution limit exceeded" error.
+#[js_function(5)]
+fn interpret_lox_async(ctx: CallContext) -> Result<JsObject> {
+    let code = ctx.get::<JsString>(0)?.into_utf8()?;
+    let code = code.as_str()?.to_string();
+
+    let mut limits = limits(&ctx)?;
+    if ctx.length > 4 && ctx.get::<JsUnknown>(4)?.get_type()? == ValueType::Object {
+        limits.cancel = Some(cancel_on_abort(ctx.env, &ctx.get::<JsObject>(4)?)?);
+    }
+    let promise = ctx.env.spawn(Interpretation { code, limits })?;
+    Ok(promise.promise_object())
+}
+
+struct Interpretation {
+    code: String,
+    limits: ExecutionLimits,
+}
+
+impl Task for Interpretation {
+    type Output = ();
+    type JsValue = JsUndefined;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        interpret(&self.code, self.limits.clone())
+    }
+
+    fn resolve(self, env: Env, _output: Self::Output) -> Result<Self::JsValue> {
+        env.get_undefined()
+    }
+}
+
+fn interpret(code: &str, limits: ExecutionLimits) -> Result<()> {
+    let mut interpreter = lox_compiler::Interpreter::new(None);
+    interpreter.set_limits(limits);
+    lox_compiler::interpret_with(code, &mut interpreter)
+        .map_err(|err| Error::from_reason(err.to_string()))
+}
+
+/// The limits passed after the code, as `timeoutMs`, `maxSteps` and `maxMemory`.
+fn limits(ctx: &CallContext) -> Result<ExecutionLimits> {
+    let max_memory = optional_number(ctx, 3)?
+        .map(|bytes| count_from(bytes, "maxMemory"))
+        .transpose()?;
+    Ok(ExecutionLimits {
+        timeout: optional_number(ctx, 1)?.map(timeout_from_ms).transpose()?,
+        max_steps: optional_number(ctx, 2)?
+            .map(|steps| count_from(steps, "maxSteps"))
+            .transpose()?,
+        // More bytes than the address space holds is no limit at all.
+        max_memory: max_memory.map(|bytes| usize::try_from(bytes).unwrap_or(usize::MAX)),
+        ..Default::default()
+    })
+}
+
+fn optional_number(ctx: &CallContext, index: usize) -> Result<Option<f64>> {
+    if index >= ctx.length || ctx.get::<JsUnknown>(index)?.get_type()? != ValueType::Number {
+        return Ok(None);
+    }
+    Ok(Some(ctx.get::<JsNumber>(index)?.get_double()?))
+}
+
+/// Negative, NaN and infinite timeouts throw instead of panicking in `Duration`.
+fn timeout_from_ms(ms: f64) -> Result<Duration> {
+    Duration::try_from_secs_f64(ms / 1000_f64).map_err(|_| {
+        Error::new(
+            Status::InvalidArg,
+            format!(
+                "Expect a non-negative number of milliseconds for timeoutMs, got {}",
+                ms
+            ),
+        )
+    })
+}
+
+/// Negative, NaN, infinite and fractional counts throw instead of being cast to a limit
+/// the caller didn't ask for.
+fn count_from(value: f64, argument: &str) -> Result<u64> {
+    if value >= 0_f64 && value < u64::MAX as f64 && value.fract() == 0_f64 {
+        return Ok(value as u64);
+    }
+    Err(Error::new(
+        Status::InvalidArg,
+        format!(
+            "Expect a non-negative integer for {}, got {}",
+            argument, value
+        ),
+    ))
+}
+
+/// A cancellation flag set once `signal` is aborted, or already set if it was.
+fn cancel_on_abort(env: &Env, signal: &JsObject) -> Result<Arc<AtomicBool>> {
+    let aborted = signal
+        .get_named_property::<JsBoolean>("aborted")?
+        .get_value()?;
+    let cancel = Arc::new(AtomicBool::new(aborted));
+
+    let flag = cancel.clone();
+    let on_abort = env.create_function_from_closure("onabort", move |ctx| {
+        flag.store(true, Ordering::Relaxed);
+        ctx.env.get_undefined()
+    })?;
+    let add_event_listener = signal.get_named_property::<JsFunction>("addEventListener")?;
+    add_event_listener.call(
+        Some(signal),
+        &[
+            env.create_string("abort")?.into_unknown(),
+            on_abort.into_unknown(),
+        ],
+    )?;
+    Ok(cancel)
+}
@@ -1,7 +1,17 @@
 extern crate wasm_bindgen;
 
+use std::time::Duration;
 use wasm_bindgen::prelude::*;
 
+/// How long a playground script may block the tab before it is stopped.
+const TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_MEMORY: usize = 64 * 1024 * 1024;
+/// Stack rustc gives a wasm32 module, the tree-walker nests on it for every call, block
+/// and subexpression.
+const WASM_STACK: usize = 1024 * 1024;
+/// What the tree-walker may use of `WASM_STACK` before a "Stack overflow" error.
+const MAX_STACK: usize = WASM_STACK / 2;
+
 #[wasm_bindgen]
 extern "C" {
     fn alert(s: &str);
@@ -29,6 +39,24 @@ pub fn set_panic_hook() {
 pub fn interpret_lox(code: String) {
     set_panic_hook();
     let now = js_sys::Date::now();
-    lox_compiler::interpret(&code, Some(log_fn));
+    let mut interpreter = lox_compiler::Interpreter::new(Some(log_fn));
+    interpreter.set_limits(lox_compiler::ExecutionLimits {
+        timeout: Some(TIMEOUT),
+        max_memory: Some(MAX_MEMORY),
+        clock: || Duration::from_secs_f64(js_sys::Date::now() / 1000_f64),
+        ..Default::default()
+    });
+    interpreter.set_max_stack(MAX_STACK);
+    // `std::time::SystemTime` is unavailable in the browser, use the JS clock instead.
+    interpreter.define_native("clock", 0, |_| {
+        Ok(lox_compiler::EnvironmentValue::Number(
+            js_sys::Date::now() / 1000_f64,
+        ))
+    });
+    if let Err(err) = lox_compiler::interpret_with(&code, &mut interpreter) {
+        // The playground parses this back into its error tabs.
+        let diagnostics = lox_compiler::diagnostics::render_json(&err, "playground.lox", &code);
+        web_sys::console::error_1(&diagnostics.into());
+    }
     web_sys::console::log_1(&format!("耗时:{}s", (js_sys::Date::now() - now) / 1000_f64).into());
 }
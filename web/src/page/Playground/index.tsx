@@ -69,11 +69,14 @@ function Playground({ show }: { show: boolean }) {
     };
 
     console.error = (...data: any[]) => {
-      let [msg] = data;
-      msg = `${
-        /\*\*\*\*\*\*[\s\S]*\*\*\*\*\*\*/i.exec(msg)?.[0] ?? 'Unknown error'
-      }`;
-      setErrors(msg.split('\n'));
+      const [msg] = data;
+      try {
+        // interpret_lox reports `{ phase, diagnostics: [{ rendered, ... }] }`.
+        const { diagnostics } = JSON.parse(msg);
+        setErrors(diagnostics.map((item: { rendered: string }) => item.rendered));
+      } catch (err) {
+        setErrors([String(msg ?? 'Unknown error')]);
+      }
       setActiveKey(PROBLEMS);
       originalError(...data);
     };
@@ -125,7 +128,11 @@ function Playground({ show }: { show: boolean }) {
                   footer={null}
                   bordered
                   dataSource={errors}
-                  renderItem={(item) => <List.Item>{item}</List.Item>}
+                  renderItem={(item) => (
+                    <List.Item>
+                      <pre style={{ margin: 0 }}>{item}</pre>
+                    </List.Item>
+                  )}
                 />
               );
             } else if (item.key === CONSOLE) {
@@ -142,7 +149,7 @@ function Playground({ show }: { show: boolean }) {
 
             let tabName = item.key;
             if (item.key === PROBLEMS && errors.length > 0) {
-              tabName += `(${errors.length - 3})`;
+              tabName += `(${errors.length})`;
             }
 
             return (